@@ -0,0 +1,380 @@
+//! Formatting options shared by every printer.
+//!
+//! Like the source bytes, the options of the file currently being formatted
+//! live in a thread local so the `format` methods don't have to carry them
+//! around.
+//!
+//! The options start from their defaults, are overridden by the closest
+//! `.format42.toml` found walking up from the formatted file, then by the
+//! command line flags. Every key of the file is optional:
+//!
+//! ```toml
+//! line_width = 80             # maximum line length
+//! tab_width = 4               # columns a tab advances to a multiple of
+//! include_order = "source"    # "source", "grouped" or "sorted"
+//! layout = "source"           # "source" or "canonical"
+//! comment_style = "source"    # "source", "block" or "line"
+//! align_file = false
+//!
+//! [header]
+//! policy = "update"           # "update", "keep" or "off"
+//! width = 80                  # 80 for the tools reading 42 headers
+//! margin = 5                  # columns between the comment marks and text
+//!
+//! [author]                    # default to `$USER` and `$MAIL`
+//! name = "marvin"
+//! mail = "marvin@42.fr"
+//!
+//! [fix]
+//! for_loops = false
+//! declarations = false
+//! comments = false
+//!
+//! [rules]                     # lint rules, all enabled by default
+//! TOO_MANY_FUNCS = false
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Name of the configuration files.
+pub const FILE_NAME: &str = ".format42.toml";
+
+/// The flags taking a value, either as the next argument or after a `=`.
+pub const VALUE_FLAGS: &[&str] = &[
+    "--line-width",
+    "--tab-width",
+    "--header",
+    "--header-width",
+    "--header-margin",
+    "--author",
+    "--mail",
+    "--enable-rule",
+    "--disable-rule",
+];
+
+thread_local! {
+    static CURRENT_CONFIG: RefCell<Config> = RefCell::new(Config::default());
+}
+
+/// How the `#include` directives of a block are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncludeOrder {
+    /// Keep the order of the source file.
+    #[default]
+    Source,
+    /// System `<...>` includes first, then local `"..."` ones, keeping the
+    /// source order inside each group.
+    Grouped,
+    /// Same as `Grouped`, each group being sorted by path.
+    Sorted,
+}
+
+/// How the elements of a file are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Header, includes, defines, type definitions, globals, prototypes and
+    /// functions, in that order.
+    Canonical,
+    /// Keep the order of the source file.
+    #[default]
+    Source,
+}
+
+/// How the comments printed on their own lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStyle {
+    /// `//` lines, unless one of the comments of the block is a `/* */`.
+    #[default]
+    Source,
+    /// A `/*` line, the text on lines starting with `** ` and a `*/` line.
+    Block,
+    /// `//` lines only.
+    Line,
+}
+
+/// What is done with the 42 header of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderPolicy {
+    /// Add one if the file has none, refresh its `Updated:` line otherwise.
+    #[default]
+    Update,
+    /// Add one if the file has none, leave it untouched otherwise.
+    Keep,
+    /// Never add one, leave the existing one untouched.
+    Off,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub include_order: IncludeOrder,
+    pub layout: Layout,
+    pub comment_style: CommentStyle,
+    /// Rewrite the forbidden `for` loops into `while` loops
+    pub fix_for_loops: bool,
+    /// Move the local declarations to the top of their function, one
+    /// variable per line, their initializers becoming assignments
+    pub fix_declarations: bool,
+    /// Move the comments of function bodies above the function
+    pub fix_comments: bool,
+    /// Align the names of every prototype, global and function of a file on
+    /// the same column, instead of one column per block
+    pub align_file: bool,
+    /// The column computed for the file being formatted when `align_file` is
+    /// set, 0 otherwise
+    pub signature_column: usize,
+    /// Maximum length of a line, 80 for the Norm
+    pub line_width: usize,
+    /// Tabs advance to the next multiple of this many columns
+    pub tab_width: usize,
+    pub header: HeaderPolicy,
+    /// Width of the header, whatever `line_width` is
+    pub header_width: usize,
+    /// Columns from the start of a header line to its text
+    pub header_margin: usize,
+    /// Name written in the header, `$USER` if unset
+    pub author: Option<String>,
+    /// Mail written in the header, `$MAIL` if unset
+    pub mail: Option<String>,
+    /// Lint rules that aren't reported
+    pub disabled_rules: BTreeSet<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            include_order: IncludeOrder::default(),
+            layout: Layout::default(),
+            comment_style: CommentStyle::default(),
+            fix_for_loops: false,
+            fix_declarations: false,
+            fix_comments: false,
+            align_file: false,
+            signature_column: 0,
+            line_width: 80,
+            tab_width: 4,
+            header: HeaderPolicy::default(),
+            header_width: 80,
+            header_margin: 5,
+            author: None,
+            mail: None,
+            disabled_rules: BTreeSet::new(),
+        }
+    }
+}
+
+/// The content of a `.format42.toml`, see the module documentation.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    line_width: Option<usize>,
+    tab_width: Option<usize>,
+    include_order: Option<IncludeOrder>,
+    layout: Option<Layout>,
+    comment_style: Option<CommentStyle>,
+    align_file: Option<bool>,
+    header: HeaderFile,
+    author: AuthorFile,
+    fix: FixFile,
+    rules: HashMap<String, bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HeaderFile {
+    policy: Option<HeaderPolicy>,
+    width: Option<usize>,
+    margin: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthorFile {
+    name: Option<String>,
+    mail: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FixFile {
+    for_loops: Option<bool>,
+    declarations: Option<bool>,
+    comments: Option<bool>,
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("`{flag}` needs a positive number, not `{value}`")),
+    }
+}
+
+fn check_rule(rule: &str) -> Result<(), String> {
+    match crate::lint::RULES.contains(&rule) {
+        true => Ok(()),
+        false => Err(format!("unknown rule `{rule}`")),
+    }
+}
+
+impl Config {
+    /// Applies a single `--flag` from the command line, the value of the
+    /// `VALUE_FLAGS` following a `=`.
+    pub fn apply_flag(&mut self, flag: &str) -> Result<(), String> {
+        if let Some((flag, value)) = flag.split_once('=') {
+            return self.apply_value(flag, value);
+        }
+        match flag {
+            "--group-includes" => self.include_order = IncludeOrder::Grouped,
+            "--sort-includes" => self.include_order = IncludeOrder::Sorted,
+            "--canonical-order" => self.layout = Layout::Canonical,
+            "--source-order" => self.layout = Layout::Source,
+            "--block-comments" => self.comment_style = CommentStyle::Block,
+            "--line-comments" => self.comment_style = CommentStyle::Line,
+            "--fix" => {
+                self.fix_for_loops = true;
+                self.fix_declarations = true;
+                self.fix_comments = true;
+            }
+            "--fix-for-loops" => self.fix_for_loops = true,
+            "--fix-declarations" => self.fix_declarations = true,
+            "--fix-comments" => self.fix_comments = true,
+            "--align-file" => self.align_file = true,
+            _ if VALUE_FLAGS.contains(&flag) => return Err(format!("`{flag}` needs a value")),
+            _ => return Err(format!("unknown option `{flag}`")),
+        }
+        Ok(())
+    }
+
+    fn apply_value(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        match flag {
+            "--line-width" => self.line_width = parse_number(flag, value)?,
+            "--tab-width" => self.tab_width = parse_number(flag, value)?,
+            "--header" => {
+                self.header = match value {
+                    "update" => HeaderPolicy::Update,
+                    "keep" => HeaderPolicy::Keep,
+                    "off" => HeaderPolicy::Off,
+                    _ => return Err(format!("unknown header policy `{value}`")),
+                }
+            }
+            "--header-width" => self.header_width = parse_number(flag, value)?,
+            "--header-margin" => self.header_margin = parse_number(flag, value)?,
+            "--author" => self.author = Some(value.to_string()),
+            "--mail" => self.mail = Some(value.to_string()),
+            "--enable-rule" => {
+                check_rule(value)?;
+                self.disabled_rules.remove(value);
+            }
+            "--disable-rule" => {
+                check_rule(value)?;
+                self.disabled_rules.insert(value.to_string());
+            }
+            _ => return Err(format!("unknown option `{flag}`")),
+        }
+        Ok(())
+    }
+
+    /// Applies the content of a `.format42.toml`.
+    fn apply_file(&mut self, file: File) -> Result<(), String> {
+        for (flag, value) in [
+            ("line_width", file.line_width),
+            ("tab_width", file.tab_width),
+            ("header.width", file.header.width),
+            ("header.margin", file.header.margin),
+        ] {
+            if value == Some(0) {
+                return Err(format!("`{flag}` must be positive"));
+            }
+        }
+        self.line_width = file.line_width.unwrap_or(self.line_width);
+        self.tab_width = file.tab_width.unwrap_or(self.tab_width);
+        self.include_order = file.include_order.unwrap_or(self.include_order);
+        self.layout = file.layout.unwrap_or(self.layout);
+        self.comment_style = file.comment_style.unwrap_or(self.comment_style);
+        self.align_file = file.align_file.unwrap_or(self.align_file);
+        self.header = file.header.policy.unwrap_or(self.header);
+        self.header_width = file.header.width.unwrap_or(self.header_width);
+        self.header_margin = file.header.margin.unwrap_or(self.header_margin);
+        self.author = file.author.name.or(self.author.take());
+        self.mail = file.author.mail.or(self.mail.take());
+        self.fix_for_loops = file.fix.for_loops.unwrap_or(self.fix_for_loops);
+        self.fix_declarations = file.fix.declarations.unwrap_or(self.fix_declarations);
+        self.fix_comments = file.fix.comments.unwrap_or(self.fix_comments);
+        for (rule, enabled) in file.rules {
+            check_rule(&rule)?;
+            match enabled {
+                true => self.disabled_rules.remove(&rule),
+                false => self.disabled_rules.insert(rule),
+            };
+        }
+        Ok(())
+    }
+
+    /// The options of the file at `path`, or of the standard input if it is
+    /// `None`: the defaults, overridden by the closest `.format42.toml` above
+    /// it, then by the command line `flags`.
+    pub fn resolve(path: Option<&Path>, flags: &[String]) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Some(file) = discover(path) {
+            let text =
+                std::fs::read_to_string(&file).map_err(|e| format!("{}: {e}", file.display()))?;
+            toml::from_str(&text)
+                .map_err(|e| e.to_string())
+                .and_then(|content| config.apply_file(content))
+                .map_err(|e| format!("{}: {}", file.display(), e.trim_end()))?;
+        }
+        for flag in flags {
+            config.apply_flag(flag)?;
+        }
+        Ok(config)
+    }
+
+    /// The name written in the header.
+    pub fn author(&self) -> String {
+        self.author
+            .clone()
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| "marvin".to_string())
+    }
+
+    /// The mail written in the header.
+    pub fn mail(&self) -> String {
+        self.mail
+            .clone()
+            .or_else(|| std::env::var("MAIL").ok())
+            .unwrap_or_else(|| "marvin@42.fr".to_string())
+    }
+}
+
+/// The closest `.format42.toml` in the directory of `path` or its parents,
+/// starting from the current directory when there is no path.
+fn discover(path: Option<&Path>) -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let start = match path {
+        Some(path) => cwd.join(path).parent()?.to_path_buf(),
+        None => cwd,
+    };
+    start
+        .ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|file| file.is_file())
+}
+
+pub fn set(config: Config) {
+    CURRENT_CONFIG.set(config);
+}
+
+pub fn update(f: impl FnOnce(&mut Config)) {
+    CURRENT_CONFIG.with_borrow_mut(f);
+}
+
+pub fn with<R>(f: impl FnOnce(&Config) -> R) -> R {
+    CURRENT_CONFIG.with_borrow(f)
+}
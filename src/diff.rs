@@ -0,0 +1,217 @@
+//! Unified diff between a file and its formatted version, for `--diff`.
+
+use std::fmt::Write;
+
+/// Lines of context printed around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// The shortest edit script turning `old` into `new`, computed with Myers'
+/// algorithm.
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    'search: for d in 0..=(n + m) {
+        // backtracking only reads the diagonals step d - 1 reached
+        trace.push(match d {
+            0 => Vec::new(),
+            _ => v[(offset - d + 1) as usize..(offset + d) as usize].to_vec(),
+        });
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = match k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                true => v[idx + 1],
+                false => v[idx - 1] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut out = Vec::with_capacity(old.len().max(new.len()));
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let at = |k: isize| v[(k + d - 1) as usize];
+        let prev_k = match k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = match d {
+            0 => 0,
+            _ => at(prev_k),
+        };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            out.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            match x == prev_x {
+                true => out.push(Edit::Insert),
+                false => out.push(Edit::Delete),
+            }
+            (x, y) = (prev_x, prev_y);
+        }
+    }
+    out.reverse();
+    out
+}
+
+/// `start,len` of a hunk range, `start` being the line before the hunk when
+/// it is empty.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// The unified diff from `old` to `new`, empty if they are the same. Lines are
+/// colored with ANSI escapes when `color` is set.
+pub fn unified(path: &str, old: &str, new: &str, color: bool) -> String {
+    let paint = |code: &str, line: &str| match color {
+        true => format!("\x1b[{code}m{line}\x1b[0m"),
+        false => line.to_string(),
+    };
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = edits(&old_lines, &new_lines);
+
+    // position in both files before each edit
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut o, mut n) = (0, 0);
+    for edit in &edits {
+        positions.push((o, n));
+        match edit {
+            Edit::Keep => (o, n) = (o + 1, n + 1),
+            Edit::Delete => o += 1,
+            Edit::Insert => n += 1,
+        }
+    }
+
+    let mut out = String::new();
+    let mut idx = 0;
+    while let Some(first) = edits[idx..].iter().position(|&e| e != Edit::Keep) {
+        let start = (idx + first).saturating_sub(CONTEXT);
+        // extend the hunk while the next change is close enough
+        let mut end = idx + first;
+        loop {
+            let changes = edits[end..]
+                .iter()
+                .take_while(|&&e| e != Edit::Keep)
+                .count();
+            let kept = edits[end + changes..]
+                .iter()
+                .take_while(|&&e| e == Edit::Keep)
+                .count();
+            end += changes;
+            if end + kept == edits.len() || kept > 2 * CONTEXT {
+                end = (end + kept.min(CONTEXT)).min(edits.len());
+                break;
+            }
+            end += kept;
+        }
+
+        if out.is_empty() {
+            writeln!(out, "{}", paint("1", &format!("--- {path}"))).unwrap();
+            writeln!(out, "{}", paint("1", &format!("+++ {path}"))).unwrap();
+        }
+        let (old_start, new_start) = positions[start];
+        let old_len = edits[start..end]
+            .iter()
+            .filter(|&&e| e != Edit::Insert)
+            .count();
+        let new_len = edits[start..end]
+            .iter()
+            .filter(|&&e| e != Edit::Delete)
+            .count();
+        let header = format!(
+            "@@ -{} +{} @@",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        );
+        writeln!(out, "{}", paint("36", &header)).unwrap();
+        for (edit, &(o, n)) in edits[start..end].iter().zip(&positions[start..end]) {
+            let (sign, line, code) = match edit {
+                Edit::Keep => (' ', old_lines[o], ""),
+                Edit::Delete => ('-', old_lines[o], "31"),
+                Edit::Insert => ('+', new_lines[n], "32"),
+            };
+            let text = format!("{sign}{}", line.strip_suffix('\n').unwrap_or(line));
+            match code {
+                "" => writeln!(out, "{text}").unwrap(),
+                code => writeln!(out, "{}", paint(code, &text)).unwrap(),
+            }
+            if !line.ends_with('\n') {
+                writeln!(out, "\\ No newline at end of file").unwrap();
+            }
+        }
+        idx = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified;
+
+    #[test]
+    fn same_or_empty_inputs() {
+        assert_eq!(unified("a.c", "", "", false), "");
+        assert_eq!(unified("a.c", "x\n", "x\n", false), "");
+        assert_eq!(
+            unified("a.c", "", "x\n", false),
+            "--- a.c\n+++ a.c\n@@ -0,0 +1,1 @@\n+x\n"
+        );
+        assert_eq!(
+            unified("a.c", "x\n", "", false),
+            "--- a.c\n+++ a.c\n@@ -1,1 +0,0 @@\n-x\n"
+        );
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        assert_eq!(
+            unified("a.c", "a\nb", "a\nb\n", false),
+            "--- a.c\n+++ a.c\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+    }
+
+    #[test]
+    fn close_changes_share_a_hunk() {
+        let lines = |changed: &[usize]| {
+            (1..=20)
+                .map(|i| match changed.contains(&i) {
+                    true => format!("changed {i}\n"),
+                    false => format!("{i}\n"),
+                })
+                .collect::<String>()
+        };
+        let diff = unified("a.c", &lines(&[]), &lines(&[3, 9]), false);
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.contains("@@ -1,12 +1,12 @@\n"));
+
+        let diff = unified("a.c", &lines(&[]), &lines(&[3, 17]), false);
+        assert_eq!(diff.matches("@@ -").count(), 2);
+        assert!(diff.contains("@@ -1,6 +1,6 @@\n"));
+        assert!(diff.contains("@@ -14,7 +14,7 @@\n"));
+    }
+}
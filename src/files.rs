@@ -0,0 +1,186 @@
+//! Finding the files to format and writing them back to the disk.
+
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::{Override, OverrideBuilder};
+
+use crate::config::Config;
+
+/// Patterns of the files picked up when walking a directory, if no
+/// `--include` is given.
+const DEFAULT_INCLUDES: &[&str] = &["*.c", "*.h"];
+
+/// Name of the gitignore-like files listing what `format42` must leave alone.
+const IGNORE_FILE: &str = ".format42ignore";
+
+/// The `--include` and `--exclude` globs, matched like `.gitignore` patterns
+/// against the paths relative to the walked directory.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Filter {
+    /// Checks that every glob is valid, so that a typo is reported once
+    /// before any file is touched.
+    pub fn validate(&self) -> Result<(), String> {
+        let root = Path::new(".");
+        self.includes(root).and(self.excludes(root)).map(drop)
+    }
+
+    /// Whitelist of the files to keep. It is matched separately from the
+    /// walk, the whitelist globs of a walk taking precedence over the ignore
+    /// files.
+    fn includes(&self, root: &Path) -> Result<Override, String> {
+        let include = match self.include.is_empty() {
+            true => DEFAULT_INCLUDES.iter().map(|s| s.to_string()).collect(),
+            false => self.include.clone(),
+        };
+        build(root, include)
+    }
+
+    fn excludes(&self, root: &Path) -> Result<Override, String> {
+        build(root, self.exclude.iter().map(|glob| format!("!{glob}")))
+    }
+}
+
+fn build(root: &Path, globs: impl IntoIterator<Item = String>) -> Result<Override, String> {
+    let mut builder = OverrideBuilder::new(root);
+    for glob in globs {
+        builder.add(&glob).map_err(|e| e.to_string())?;
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Expands the paths given on the command line into the files to format.
+/// Files are kept as is, directories are walked recursively, skipping hidden
+/// entries and what `.gitignore` or `.format42ignore` files ignore, and only
+/// keeping the files matched by `filter`.
+pub fn expand(args: &[OsString], filter: &Filter) -> Vec<Result<PathBuf, String>> {
+    let mut out = Vec::new();
+    for arg in args {
+        let root = Path::new(arg);
+        if !root.is_dir() {
+            out.push(Ok(root.to_path_buf()));
+            continue;
+        }
+        let (includes, excludes) = match filter
+            .includes(root)
+            .and_then(|includes| filter.excludes(root).map(|excludes| (includes, excludes)))
+        {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                out.push(Err(e));
+                continue;
+            }
+        };
+        let walk = ignore::WalkBuilder::new(root)
+            .add_custom_ignore_filename(IGNORE_FILE)
+            .require_git(false)
+            .overrides(excludes)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walk {
+            match entry {
+                Ok(entry)
+                    if entry.file_type().is_some_and(|t| t.is_file())
+                        && includes.matched(entry.path(), false).is_whitelist() =>
+                {
+                    out.push(Ok(entry.into_path()))
+                }
+                Ok(_) => (),
+                Err(e) => out.push(Err(e.to_string())),
+            }
+        }
+    }
+    out
+}
+
+/// A file to format or lint, read and with its options resolved.
+pub struct Input {
+    /// Where it was read from, or the `--stdin-filepath` of stdin
+    pub path: PathBuf,
+    /// The name written in its header
+    pub filename: String,
+    pub data: Vec<u8>,
+    pub config: Config,
+}
+
+/// The files given on the command line as `expand` finds them, or stdin if
+/// there is none, each with the options of its configuration file overridden
+/// by the command line `flags`.
+///
+/// stdin is read as if it was the content of `stdin_path`, which gives the
+/// name written in the header and where the configuration is looked for.
+pub fn inputs<'a>(
+    args: &'a [OsString],
+    filter: &Filter,
+    flags: &'a [String],
+    stdin_path: Option<&'a Path>,
+) -> impl Iterator<Item = Result<Input, String>> + 'a {
+    let stdin = args.is_empty().then(|| {
+        let mut data = Vec::with_capacity(1024);
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut data)
+            .map(|_| (stdin_path.unwrap_or("<stdin>".as_ref()).to_path_buf(), data))
+            .map_err(|e| format!("<stdin>: {e}"))
+    });
+    let files = expand(args, filter).into_iter().map(|path| {
+        let path = path?;
+        std::fs::read(&path)
+            .map(|data| (path.clone(), data))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    });
+    stdin.into_iter().chain(files).map(move |file| {
+        let (path, data) = file?;
+        let config_path = match args.is_empty() {
+            true => stdin_path,
+            false => Some(path.as_path()),
+        };
+        let config = Config::resolve(config_path, flags)?;
+        let filename = match args.is_empty() && stdin_path.is_none() {
+            true => "new_file.c",
+            false => path
+                .file_name()
+                .and_then(std::ffi::OsStr::to_str)
+                .unwrap_or("<new file>"),
+        };
+        Ok(Input {
+            filename: filename.to_string(),
+            path,
+            data,
+            config,
+        })
+    })
+}
+
+/// Replaces the content of `path` by `data`, going through a temporary file
+/// renamed over it so that the file is never left half written. The
+/// permissions of the file are kept, and a symbolic link is kept as is, its
+/// target being replaced instead.
+pub fn write_in_place(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let path = std::fs::canonicalize(path)?;
+    let permissions = std::fs::metadata(&path)?.permissions();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temporary)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::set_permissions(&temporary, permissions)?;
+        std::fs::rename(&temporary, &path)
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
@@ -0,0 +1,1315 @@
+//! Printer for function definitions and the statements of their bodies.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use tree_sitter::Node;
+
+use crate::config;
+use crate::syntax::{self, collapsed, expression, text};
+use crate::{
+    align_tabs_from, comment_source, format_comments, is_directive_line, is_trailing,
+    trailing_comment, write_commented, CommentBlock, FunctionDefinition, TypeDefinition,
+};
+
+fn tabs(ident: usize, fmt: &mut impl Write) -> std::io::Result<()> {
+    for _ in 0..ident {
+        write!(fmt, "\t")?;
+    }
+    Ok(())
+}
+
+/// State carried while printing the statements of a function.
+#[derive(Debug, Clone, Default)]
+struct Context {
+    /// Statements a `continue` has to run first: the step of the `for` loop
+    /// it belongs to, when that loop is rewritten into a `while`.
+    continue_step: Vec<String>,
+    /// The declarations printed at the top of the function, see `Locals`
+    hoisted: Rc<HashMap<usize, bool>>,
+}
+
+impl Context {
+    /// The context of the body of a new loop, `continue` now refers to it.
+    fn in_loop(&self, step: Vec<String>) -> Self {
+        Self {
+            continue_step: step,
+            hoisted: self.hoisted.clone(),
+        }
+    }
+}
+
+/// The return type of a function and its declarator (name + parameters),
+/// meant to be printed separated by tabs.
+pub fn signature(def: Node<'_>) -> (String, String) {
+    let end = def
+        .child_by_field_name("declarator")
+        .map_or(def.start_byte(), |d| d.end_byte());
+    if let Some(commented) = syntax::commented_declarators(def, end) {
+        return commented;
+    }
+    let ty = syntax::specifiers(def);
+    let decl = def
+        .child_by_field_name("declarator")
+        .map(syntax::declarator)
+        .unwrap_or_default();
+    (ty, decl)
+}
+
+/// The type of a local `declaration` and its declarators, `name, other;`,
+/// kept as written when they hold a comment.
+pub fn declaration(node: Node<'_>) -> (String, String) {
+    if let Some(commented) = syntax::commented_declarators(node, node.end_byte()) {
+        return commented;
+    }
+    let decls = syntax::declarators(node)
+        .into_iter()
+        .map(syntax::declarator)
+        .collect::<Vec<_>>();
+    (syntax::specifiers(node), format!("{};", decls.join(", ")))
+}
+
+/// Splits the top level `a, b` of a comma expression into one statement
+/// each.
+fn split_comma(node: Node<'_>, out: &mut Vec<String>) {
+    match node.kind() {
+        "comma_expression" => {
+            for side in ["left", "right"] {
+                if let Some(side) = node.child_by_field_name(side) {
+                    split_comma(side, out);
+                }
+            }
+        }
+        _ => out.push(format!("{};", expression(node))),
+    }
+}
+
+/// The `name = value;` assignments replacing the initializers of a
+/// declaration moved to the top of the function.
+fn assignments(node: Node<'_>) -> Vec<String> {
+    let mut out = Vec::new();
+    for decl in syntax::declarators(node) {
+        let value = decl.child_by_field_name("value");
+        let name = decl
+            .child_by_field_name("declarator")
+            .and_then(syntax::declarator_name);
+        if let (Some(name), Some(value)) = (name, value) {
+            out.push(format!("{} = {};", text(name), expression(value)));
+        }
+    }
+    out
+}
+
+/// Whether the initializers of a declaration can become assignments: not
+/// for `static` variables, which are only initialized once, nor for
+/// constants and arrays, which can't be assigned. Declarations holding a
+/// comment are kept whole too, so that it isn't lost.
+fn splittable(node: Node<'_>) -> bool {
+    if syntax::has_comment(node) {
+        return false;
+    }
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+    if children.iter().any(|n| text(*n) == "static") {
+        return false;
+    }
+    let const_type = children
+        .iter()
+        .any(|n| n.kind() == "type_qualifier" && text(*n) == "const");
+    syntax::declarators(node).into_iter().all(|decl| {
+        let (Some(inner), Some(value)) = (
+            decl.child_by_field_name("declarator"),
+            decl.child_by_field_name("value"),
+        ) else {
+            return true;
+        };
+        let const_variable = match inner.kind() {
+            "pointer_declarator" => {
+                let mut cursor = inner.walk();
+                let qualified = inner
+                    .named_children(&mut cursor)
+                    .any(|n| n.kind() == "type_qualifier" && text(n) == "const");
+                qualified
+            }
+            _ => const_type,
+        };
+        !const_variable && inner.kind() != "array_declarator" && value.kind() != "initializer_list"
+    })
+}
+
+/// The variables declared at the top of a function body.
+#[derive(Default)]
+struct Locals<'ts> {
+    /// The comments written among the leading declarations
+    comments: CommentBlock<'ts>,
+    /// `type\t`, `name;` and trailing comment of each line
+    lines: Vec<(String, String, Option<Node<'ts>>)>,
+    /// The `declaration` and `comment` nodes printed here instead of where
+    /// they are written, and whether initializers stay there as assignments
+    hoisted: HashMap<usize, bool>,
+}
+
+/// A declaration of a function body, its names being visible from where it
+/// is written to the end of `scope`.
+struct Scope<'ts> {
+    decl: Node<'ts>,
+    names: Vec<&'static str>,
+    scope: Node<'ts>,
+}
+
+impl Scope<'_> {
+    fn new(decl: Node<'_>) -> Scope<'_> {
+        let mut scope = decl.parent().unwrap_or(decl);
+        while matches!(scope.kind(), "case_statement" | "labeled_statement")
+            || scope.kind().starts_with("preproc_")
+        {
+            scope = scope.parent().unwrap_or(scope);
+        }
+        let names = syntax::declarators(decl)
+            .into_iter()
+            .map(|node| match node.kind() {
+                "init_declarator" => node.child_by_field_name("declarator").unwrap_or(node),
+                _ => node,
+            })
+            .map(|node| syntax::declarator_name(node).map(text).unwrap_or_default())
+            .collect();
+        Scope { decl, names, scope }
+    }
+
+    /// Whether `name`, written at `byte`, refers to this declaration.
+    fn covers(&self, name: &str, byte: usize) -> bool {
+        self.names.contains(&name)
+            && (self.decl.start_byte()..self.scope.end_byte()).contains(&byte)
+    }
+}
+
+impl<'ts> Locals<'ts> {
+    /// Collects the leading declarations of `body`, and depending on the
+    /// fixes enabled the ones of its `for` loops or all the other ones.
+    ///
+    /// A declaration is only moved when its names can't refer to anything
+    /// else from the top of the function: they mustn't be parameters,
+    /// shadow another declaration, be used outside of the declarations of
+    /// the same name or be declared in the body further down.
+    fn collect(def: Node<'ts>, body: Node<'ts>) -> Self {
+        let (fix_declarations, fix_for_loops) =
+            config::with(|c| (c.fix_declarations, c.fix_for_loops));
+        let mut locals = Self::default();
+
+        let mut declarator = def.child_by_field_name("declarator");
+        while let Some(node) = declarator.filter(|n| n.kind() != "function_declarator") {
+            declarator = node.child_by_field_name("declarator");
+        }
+        let mut params = Vec::new();
+        if let Some(list) = declarator.and_then(|d| d.child_by_field_name("parameters")) {
+            let mut cursor = list.walk();
+            params.extend(
+                list.named_children(&mut cursor)
+                    .filter_map(|param| param.child_by_field_name("declarator"))
+                    .filter_map(syntax::declarator_name)
+                    .map(text),
+            );
+        }
+
+        let mut cursor = body.walk();
+        let mut leading = Vec::new();
+        let mut comments = Vec::new();
+        for child in body.named_children(&mut cursor) {
+            match child.kind() {
+                "comment" if is_trailing(child) => (),
+                "comment" => comments.push(child),
+                "declaration" => {
+                    leading.push(child);
+                    for comment in comments.drain(..) {
+                        locals.hoisted.insert(comment.id(), false);
+                        locals.comments.0.push(comment);
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let mut nodes = Vec::new();
+        find_kind(body, "declaration", &mut nodes);
+        let scopes = nodes.into_iter().map(Scope::new).collect::<Vec<_>>();
+        let mut uses = Vec::new();
+        find_kind(body, "identifier", &mut uses);
+        let free = uses
+            .into_iter()
+            .map(|node| (text(node), node.start_byte()))
+            .filter(|&(name, byte)| !scopes.iter().any(|s| s.covers(name, byte)))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        // what the types and sizes of the declarations may depend on
+        let mut locals_names = params.clone();
+        locals_names.extend(scopes.iter().flat_map(|s| s.names.iter().copied()));
+        locals_names.extend(local_types(body));
+        let movable = |local: &Scope<'_>| {
+            !dependencies(local.decl)
+                .iter()
+                .any(|name| locals_names.contains(name))
+                && local.names.iter().all(|name| {
+                    !params.contains(name)
+                        && !free.contains(name)
+                        && !scopes.iter().any(|other| {
+                            other.decl != local.decl && other.covers(name, local.decl.start_byte())
+                        })
+                })
+        };
+        let scope = |decl: Node<'ts>| scopes.iter().find(|s| s.decl == decl).unwrap();
+
+        let mut nested = Vec::new();
+        if fix_declarations || fix_for_loops {
+            nested_declarations(body, fix_declarations, &mut nested);
+        }
+        // the line of each name moved, two variables being merged into one
+        // only when declared the same way
+        let mut lines = HashMap::new();
+        let mut moved = Vec::new();
+        for decl in nested.into_iter().filter(|d| !leading.contains(d)) {
+            let local = scope(decl);
+            let merged = local
+                .names
+                .iter()
+                .zip(declared_lines(decl))
+                .all(|(name, line)| lines.get(name).is_none_or(|l| *l == line));
+            if splittable(decl) && movable(local) && merged {
+                lines.extend(local.names.iter().copied().zip(declared_lines(decl)));
+                moved.push(decl);
+            }
+        }
+        // the declarations staying in the body itself would be declared twice
+        loop {
+            let staying = scopes
+                .iter()
+                .filter(|s| s.scope == body)
+                .filter(|s| !leading.contains(&s.decl) && !moved.contains(&s.decl))
+                .flat_map(|s| s.names.iter().copied())
+                .collect::<Vec<_>>();
+            let count = moved.len();
+            moved.retain(|&decl| !scope(decl).names.iter().any(|n| staying.contains(n)));
+            if moved.len() == count {
+                break;
+            }
+        }
+
+        // an initializer only moves below the leading declarations when none
+        // of the following ones needs its value
+        let mut needed = Vec::new();
+        let mut split = HashMap::new();
+        for &decl in leading.iter().rev() {
+            let local = scope(decl);
+            let can_split = fix_declarations
+                && splittable(decl)
+                && movable(local)
+                && !local.names.iter().any(|name| needed.contains(name));
+            match can_split {
+                true => needed.extend(dependencies(decl)),
+                false => {
+                    let mut uses = Vec::new();
+                    find_kind(decl, "identifier", &mut uses);
+                    needed.extend(uses.into_iter().map(text));
+                }
+            }
+            split.insert(decl.id(), can_split);
+        }
+        for &decl in &leading {
+            if !fix_declarations || syntax::has_comment(decl) {
+                // kept whole, as written
+                let (ty, decls) = declaration(decl);
+                locals
+                    .lines
+                    .push((format!("{ty}\t"), decls, trailing_comment(decl)));
+                locals.hoisted.insert(decl.id(), false);
+            } else {
+                locals.push(decl, split[&decl.id()]);
+            }
+        }
+        for decl in moved {
+            locals.push(decl, true);
+        }
+        locals
+    }
+
+    /// Adds one line per variable of `decl`, the initializers staying where
+    /// they are as assignments when `split`, the lines keeping them
+    /// otherwise.
+    ///
+    /// The trailing comment of `decl` follows its last line, unless it stays
+    /// with the assignments.
+    fn push(&mut self, decl: Node<'ts>, split: bool) {
+        let ty = syntax::specifiers(decl);
+        let pushed = self.lines.len();
+        for node in syntax::declarators(decl) {
+            let node = match (split, node.kind()) {
+                (true, "init_declarator") => node.child_by_field_name("declarator").unwrap_or(node),
+                _ => node,
+            };
+            let (ty, decl) = (format!("{ty}\t"), format!("{};", syntax::declarator(node)));
+            if !self.lines.iter().any(|(t, d, _)| (t, d) == (&ty, &decl)) {
+                self.lines.push((ty, decl, None));
+            }
+        }
+        if !split || assignments(decl).is_empty() {
+            if let Some(last) = self.lines[pushed..].last_mut() {
+                last.2 = trailing_comment(decl);
+            }
+        }
+        self.hoisted.insert(decl.id(), split);
+    }
+}
+
+/// The line declaring each variable of `decl` without its initializer.
+fn declared_lines(decl: Node<'_>) -> Vec<String> {
+    let ty = syntax::specifiers(decl);
+    syntax::declarators(decl)
+        .into_iter()
+        .map(|node| match node.kind() {
+            "init_declarator" => node.child_by_field_name("declarator").unwrap_or(node),
+            _ => node,
+        })
+        .map(|node| format!("{ty}\t{};", syntax::declarator(node)))
+        .collect()
+}
+
+/// The names the type of `decl` and the sizes of its declarators refer to,
+/// its initializers left out.
+fn dependencies(decl: Node<'_>) -> Vec<&'static str> {
+    let declarators = syntax::declarators(decl);
+    let mut cursor = decl.walk();
+    let mut found = Vec::new();
+    for child in decl.named_children(&mut cursor) {
+        let (node, name) = match child.kind() {
+            _ if !declarators.contains(&child) => (child, None),
+            "init_declarator" => {
+                let inner = child.child_by_field_name("declarator").unwrap_or(child);
+                (inner, syntax::declarator_name(inner))
+            }
+            _ => (child, syntax::declarator_name(child)),
+        };
+        let mut names = vec![node];
+        find_kind(node, "identifier", &mut names);
+        find_kind(node, "type_identifier", &mut names);
+        found.extend(
+            names
+                .into_iter()
+                .filter(|&n| matches!(n.kind(), "identifier" | "type_identifier"))
+                .filter(|&n| Some(n) != name),
+        );
+    }
+    found.into_iter().map(text).collect()
+}
+
+/// The names of the types, tags and enumerators defined in a function body.
+fn local_types(body: Node<'_>) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    let mut nodes = Vec::new();
+    find_kind(body, "type_definition", &mut nodes);
+    for def in nodes.drain(..) {
+        names.extend(
+            syntax::declarators(def)
+                .into_iter()
+                .filter_map(syntax::declarator_name)
+                .map(text),
+        );
+    }
+    for kind in [
+        "struct_specifier",
+        "union_specifier",
+        "enum_specifier",
+        "enumerator",
+    ] {
+        find_kind(body, kind, &mut nodes);
+    }
+    names.extend(
+        nodes
+            .into_iter()
+            .filter(|n| n.kind() == "enumerator" || n.child_by_field_name("body").is_some())
+            .filter_map(|n| n.child_by_field_name("name"))
+            .map(text),
+    );
+    names
+}
+
+/// Every node of `kind` under `node`.
+fn find_kind<'ts>(node: Node<'ts>, kind: &str, out: &mut Vec<Node<'ts>>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if child.kind() == kind {
+            out.push(child);
+        }
+        find_kind(child, kind, out);
+    }
+}
+
+/// The declarations of a function body that may be moved to its top: all of
+/// them, or only the ones of its `for (int i = 0; ...)` loops.
+fn nested_declarations<'ts>(node: Node<'ts>, all: bool, out: &mut Vec<Node<'ts>>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        let for_init = node.kind() == "for_statement"
+            && node.child_by_field_name("initializer") == Some(child);
+        if child.kind() == "declaration" && (all || for_init) {
+            out.push(child);
+        } else if !loses_comment(child) && !child.kind().starts_with("preproc_") {
+            // statements printed as written keep their declarations
+            nested_declarations(child, all, out);
+        }
+    }
+}
+
+/// A short description of a statement for the comments moved out of a
+/// function: its first line, without the opening brace nor the comments.
+fn summary(node: Node<'_>) -> String {
+    const MAX_LEN: usize = 40;
+    let line = text(node).lines().next().unwrap_or_default();
+    let mut comments = Vec::new();
+    find_kind(node, "comment", &mut comments);
+    let line = match comments.first().map(|c| c.start_byte() - node.start_byte()) {
+        Some(start) if start < line.len() => &line[..start],
+        _ => line,
+    };
+    let line = line.trim().trim_end_matches('{').trim_end();
+    match line.char_indices().nth(MAX_LEN) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// The comments written in the blocks and control statements of a function
+/// body, which the Norm forbids, along with where they were found.
+fn body_comments<'ts>(node: Node<'ts>, out: &mut Vec<(Node<'ts>, String)>) {
+    let mut cursor = node.walk();
+    let children = node.named_children(&mut cursor).collect::<Vec<_>>();
+    let in_block = matches!(node.kind(), "compound_statement" | "case_statement");
+    // `if (a) // why`: printed on the lines of the statement by `head`
+    let in_statement = matches!(
+        node.kind(),
+        "if_statement"
+            | "else_clause"
+            | "while_statement"
+            | "do_statement"
+            | "for_statement"
+            | "switch_statement"
+            | "labeled_statement"
+    );
+    for (idx, &child) in children.iter().enumerate() {
+        if child.kind() != "comment" {
+            // statements printed as written keep their comments
+            if !loses_comment(child) {
+                body_comments(child, out);
+            }
+            continue;
+        }
+        if in_statement {
+            out.push((child, format!("in `{}`", summary(node))));
+            continue;
+        }
+        if !in_block {
+            continue;
+        }
+        let previous = children[..idx]
+            .iter()
+            .rev()
+            .find(|n| n.kind() != "comment")
+            .filter(|&&n| Some(n) != node.child_by_field_name("value"));
+        let next = children[idx + 1..].iter().find(|n| n.kind() != "comment");
+        let enclosing = match node.kind() {
+            "case_statement" => Some(node),
+            _ => node.parent(),
+        };
+        let location = match (next, enclosing.map(|n| n.kind())) {
+            _ if previous.is_some_and(|p| p.end_position().row == child.start_position().row) => {
+                format!("after `{}`", summary(*previous.unwrap()))
+            }
+            (Some(&next), _) => format!("before `{}`", summary(next)),
+            (None, Some("function_definition")) => "at the end of the function".to_string(),
+            (None, Some("compound_statement") | None) => "at the end of a block".to_string(),
+            (None, Some(_)) => format!("at the end of `{}`", summary(enclosing.unwrap())),
+        };
+        out.push((child, location));
+    }
+}
+
+/// The comments of the functions under `node` that `--fix-comments` leaves
+/// in their body, the ones of the statements printed as written.
+pub fn kept_comments<'ts>(node: Node<'ts>) -> Vec<Node<'ts>> {
+    let mut functions = Vec::new();
+    find_kind(node, "function_definition", &mut functions);
+    let mut kept = Vec::new();
+    for body in functions
+        .into_iter()
+        .filter_map(|f| f.child_by_field_name("body"))
+    {
+        let mut moved = Vec::new();
+        body_comments(body, &mut moved);
+        let mut comments = Vec::new();
+        find_kind(body, "comment", &mut comments);
+        kept.extend(
+            comments
+                .into_iter()
+                .filter(|c| moved.iter().all(|(m, _)| m != c)),
+        );
+    }
+    kept
+}
+
+impl<'ts> FunctionDefinition<'ts> {
+    /// Prints the function, the name in its signature and the names of its
+    /// local variables aligned on the same column, the one of the whole file
+    /// with `--align-file`.
+    ///
+    /// With `--fix-comments` the comments of the body are printed after the
+    /// ones above the function, each followed by where it was written.
+    pub fn format(&self, ident: usize, fmt: &mut impl Write) -> std::io::Result<()> {
+        let (ty, decl) = signature(self.1);
+        let mut head = format!("{}{ty}\t", "\t".repeat(ident));
+        let Some(body) = self.1.child_by_field_name("body") else {
+            self.0.format(ident, fmt)?;
+            return writeln!(fmt, "{head}{decl}");
+        };
+        let Locals {
+            mut comments,
+            mut lines,
+            mut hoisted,
+            ..
+        } = Locals::collect(self.1, body);
+        let mut moved = Vec::new();
+        if config::with(|c| c.fix_comments) {
+            body_comments(body, &mut moved);
+            for (comment, _) in &moved {
+                hoisted.insert(comment.id(), false);
+            }
+            // the comments among the leading declarations are moved too
+            comments = CommentBlock::default();
+        }
+        if moved.is_empty() {
+            self.0.format(ident, fmt)?;
+        } else {
+            let CommentBlock(above) = &self.0;
+            let mut texts = above
+                .iter()
+                .map(|&n| comment_source(n).to_string())
+                .collect::<Vec<_>>();
+            for (comment, location) in moved {
+                texts.push(comment_source(comment).to_string());
+                texts.push(format!("// ({location})"));
+            }
+            let texts = texts.iter().map(String::as_str).collect::<Vec<_>>();
+            format_comments(&texts, ident, fmt)?;
+        }
+        let ctx = Context {
+            hoisted: Rc::new(hoisted),
+            ..Context::default()
+        };
+        let mut instructions = Vec::new();
+        statements(body, ident + 1, &ctx, &mut instructions)?;
+
+        for (ty, _, _) in &mut lines {
+            ty.insert_str(0, &"\t".repeat(ident + 1));
+        }
+        align_tabs_from(
+            std::iter::once(&mut head).chain(lines.iter_mut().map(|(ty, _, _)| ty)),
+            config::with(|c| c.signature_column),
+        );
+        writeln!(fmt, "{head}{decl}")?;
+        tabs(ident, fmt)?;
+        writeln!(fmt, "{{")?;
+        comments.format(ident + 1, fmt)?;
+        let declarations = lines
+            .iter()
+            .map(|(ty, decl, comment)| {
+                let comment = comment.filter(|c| !ctx.hoisted.contains_key(&c.id()));
+                (format!("{ty}{decl}"), comment.map(text))
+            })
+            .collect::<Vec<_>>();
+        write_commented(&declarations, fmt)?;
+        if !lines.is_empty() && !instructions.is_empty() {
+            writeln!(fmt)?;
+        }
+        fmt.write_all(&instructions)?;
+        let end = trailing_comment(self.1).map(text);
+        write_commented(&[(format!("{}}}", "\t".repeat(ident)), end)], fmt)
+    }
+}
+
+/// Prints a `compound_statement` with Allman braces, the braces at `ident`
+/// and its content one level deeper.
+fn block(node: Node<'_>, ident: usize, ctx: &Context, fmt: &mut impl Write) -> std::io::Result<()> {
+    tabs(ident, fmt)?;
+    writeln!(fmt, "{{")?;
+    statements(node, ident + 1, ctx, fmt)?;
+    tabs(ident, fmt)?;
+    writeln!(fmt, "}}")
+}
+
+/// Prints the content of a `compound_statement`, without its braces.
+fn statements(
+    node: Node<'_>,
+    ident: usize,
+    ctx: &Context,
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut cursor = node.walk();
+    let children = node.named_children(&mut cursor).collect::<Vec<_>>();
+    statement_list(children, ident, ctx, fmt)
+}
+
+/// Prints statements, the comments between them above the next one and the
+/// comments ending their line after them.
+fn statement_list(
+    children: Vec<Node<'_>>,
+    ident: usize,
+    ctx: &Context,
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut comments = CommentBlock::default();
+    let mut lines = Vec::new();
+    for child in children {
+        if child.kind() == "comment" {
+            if !is_trailing(child) && !ctx.hoisted.contains_key(&child.id()) {
+                comments.0.push(child);
+            }
+            continue;
+        }
+        if !comments.0.is_empty() {
+            write_commented(&std::mem::take(&mut lines), fmt)?;
+            std::mem::take(&mut comments).format(ident, fmt)?;
+        }
+        let mut buffer = Vec::new();
+        statement(child, ident, ctx, &mut buffer)?;
+        let code = String::from_utf8(buffer).unwrap();
+        let Some(code) = code.strip_suffix('\n') else {
+            // a declaration printed at the top of the function
+            continue;
+        };
+        let comment = trailing_comment(child)
+            // directives are printed as written, comment included
+            .filter(|_| !is_directive_line(child))
+            // so are the types spanning several lines
+            .filter(|_| !local_type(child).is_some_and(|def| def.has_body()))
+            .filter(|c| !ctx.hoisted.contains_key(&c.id()))
+            .map(text);
+        lines.push((code.to_string(), comment));
+    }
+    write_commented(&lines, fmt)?;
+    comments.format(ident, fmt)
+}
+
+/// Prints the body of an `if`, `while`... : blocks stay at the level of the
+/// keyword, single statements are indented once more.
+fn body(node: Node<'_>, ident: usize, ctx: &Context, fmt: &mut impl Write) -> std::io::Result<()> {
+    if node.kind() == "compound_statement" {
+        block(node, ident, ctx, fmt)
+    } else if node.kind() == "continue_statement" && !ctx.continue_step.is_empty() {
+        // the step has to run first, which takes more than one statement
+        tabs(ident, fmt)?;
+        writeln!(fmt, "{{")?;
+        statement(node, ident + 1, ctx, fmt)?;
+        tabs(ident, fmt)?;
+        writeln!(fmt, "}}")
+    } else {
+        statement(node, ident + 1, ctx, fmt)
+    }
+}
+
+/// Whether a comment is written inside the expressions of a statement, where
+/// only printing the statement as written keeps it.
+fn loses_comment(node: Node<'_>) -> bool {
+    let fields: &[&str] = match node.kind() {
+        "if_statement" | "while_statement" | "do_statement" | "switch_statement" => &["condition"],
+        "for_statement" => &["initializer", "condition", "update"],
+        "case_statement" => &["value"],
+        "compound_statement" | "labeled_statement" | "else_clause" | "declaration" => &[],
+        kind if kind.starts_with("preproc_") => &[],
+        _ => return syntax::has_comment(node),
+    };
+    fields
+        .iter()
+        .filter_map(|&field| node.child_by_field_name(field))
+        .any(syntax::has_comment)
+}
+
+/// Whether a `continue` of the current loop is written in `node`.
+fn continues(node: Node<'_>) -> bool {
+    match node.kind() {
+        "continue_statement" => true,
+        "for_statement" | "while_statement" | "do_statement" => false,
+        _ => {
+            let mut cursor = node.walk();
+            let continues = node.children(&mut cursor).any(continues);
+            continues
+        }
+    }
+}
+
+/// Whether a `continue` of the current loop is in a statement printed as
+/// written, where the step of a `for` can't be added before it.
+fn continue_as_written(node: Node<'_>) -> bool {
+    if loses_comment(node) {
+        return continues(node);
+    }
+    match node.kind() {
+        "for_statement" | "while_statement" | "do_statement" => false,
+        _ => {
+            let mut cursor = node.walk();
+            let found = node.children(&mut cursor).any(continue_as_written);
+            found
+        }
+    }
+}
+
+/// Whether the body of a `for` declares a name its step uses, which would
+/// then refer to that declaration once the step is moved in the body.
+fn step_shadowed(node: Node<'_>) -> bool {
+    let (Some(body), Some(update)) = (
+        node.child_by_field_name("body"),
+        node.child_by_field_name("update"),
+    ) else {
+        return false;
+    };
+    let mut decls = Vec::new();
+    find_kind(body, "declaration", &mut decls);
+    let mut uses = vec![update];
+    find_kind(update, "identifier", &mut uses);
+    decls.into_iter().map(Scope::new).any(|decl| {
+        uses.iter()
+            .any(|&n| n.kind() == "identifier" && decl.names.contains(&text(n)))
+    })
+}
+
+/// The comments written directly in a control statement (`if (a) // why`),
+/// the ones before its `body` and the ones after it, unless moved out of
+/// the function.
+fn own_comments<'ts>(
+    node: Node<'ts>,
+    body: Option<Node<'ts>>,
+    ctx: &Context,
+) -> (Vec<&'static str>, Vec<Node<'ts>>) {
+    let mut cursor = node.walk();
+    let (before, after): (Vec<_>, Vec<_>) = node
+        .children(&mut cursor)
+        .filter(|n| n.kind() == "comment" && !ctx.hoisted.contains_key(&n.id()))
+        .partition(|n| body.is_none_or(|b| n.start_byte() < b.start_byte()));
+    (before.into_iter().map(text).collect(), after)
+}
+
+/// Prints the body of a control statement like `body` does, followed by
+/// the comments written after it.
+fn commented_body(
+    node: Node<'_>,
+    ident: usize,
+    ctx: &Context,
+    comments: &[Node<'_>],
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    let comments = comments.iter().map(|&n| text(n)).collect::<Vec<_>>();
+    if comments.is_empty() {
+        return body(node, ident, ctx, fmt);
+    }
+    let mut buffer = Vec::new();
+    body(node, ident, ctx, &mut buffer)?;
+    let code = String::from_utf8(buffer).unwrap();
+    let code = code.strip_suffix('\n').unwrap_or(&code).to_string();
+    match comments[..] {
+        [comment] => write_commented(&[(code, Some(comment))], fmt),
+        _ => {
+            writeln!(fmt, "{code}")?;
+            format_comments(&comments, ident, fmt)
+        }
+    }
+}
+
+/// Prints the line opening a control statement at `ident`, followed by the
+/// comments written on it, the ones that don't fit going above it.
+fn head(
+    line: String,
+    comments: &[&str],
+    ident: usize,
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    let line = format!("{}{line}", "\t".repeat(ident));
+    match comments {
+        [] => writeln!(fmt, "{line}"),
+        [comment] => write_commented(&[(line, Some(comment))], fmt),
+        comments => {
+            format_comments(comments, ident, fmt)?;
+            writeln!(fmt, "{line}")
+        }
+    }
+}
+
+fn condition(node: Node<'_>) -> String {
+    node.child_by_field_name("condition")
+        .map(|cond| match cond.kind() {
+            "parenthesized_expression" => expression(cond),
+            _ => format!("({})", expression(cond)),
+        })
+        .unwrap_or_default()
+}
+
+/// Prints `for (init; cond; step) body` as
+/// `init; while (cond) { body; step; }`, the declarations of `init` having
+/// been hoisted to the top of the function when possible. Otherwise the
+/// whole loop goes in its own block, where they can't collide with the
+/// variables around it.
+fn for_as_while(
+    node: Node<'_>,
+    ident: usize,
+    ctx: &Context,
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut init = Vec::new();
+    let mut scoped = false;
+    match node.child_by_field_name("initializer") {
+        Some(decl) if decl.kind() == "declaration" => match ctx.hoisted.get(&decl.id()) {
+            Some(true) => init = assignments(decl),
+            _ => {
+                let (ty, decls) = declaration(decl);
+                init.push(format!("{ty}\t{decls}"));
+                scoped = true;
+            }
+        },
+        Some(expr) => split_comma(expr, &mut init),
+        None => (),
+    }
+    if scoped {
+        tabs(ident, fmt)?;
+        writeln!(fmt, "{{")?;
+    }
+    let outer = ident;
+    let ident = ident + scoped as usize;
+    let mut step = Vec::new();
+    if let Some(update) = node.child_by_field_name("update") {
+        split_comma(update, &mut step);
+    }
+    let cond = node
+        .child_by_field_name("condition")
+        .map(expression)
+        .unwrap_or_else(|| "1".to_string());
+
+    for line in &init {
+        tabs(ident, fmt)?;
+        writeln!(fmt, "{line}")?;
+    }
+    let (comments, _) = own_comments(node, node.child_by_field_name("body"), ctx);
+    head(format!("while ({cond})"), &comments, ident, fmt)?;
+    tabs(ident, fmt)?;
+    writeln!(fmt, "{{")?;
+    let inner = ctx.in_loop(step.clone());
+    match node.child_by_field_name("body") {
+        Some(b) if b.kind() == "compound_statement" => statements(b, ident + 1, &inner, fmt)?,
+        // `for (...);`: nothing but the step to run
+        Some(b) if b.kind() == "expression_statement" && b.named_child_count() == 0 => (),
+        Some(b) => statement(b, ident + 1, &inner, fmt)?,
+        None => (),
+    }
+    for line in &step {
+        tabs(ident + 1, fmt)?;
+        writeln!(fmt, "{line}")?;
+    }
+    tabs(ident, fmt)?;
+    writeln!(fmt, "}}")?;
+    if scoped {
+        tabs(outer, fmt)?;
+        writeln!(fmt, "}}")?;
+    }
+    Ok(())
+}
+
+fn statement(
+    node: Node<'_>,
+    ident: usize,
+    ctx: &Context,
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    if node.kind().starts_with("preproc_") {
+        // directives always start at the first column
+        return writeln!(fmt, "{}", text(node).trim_end());
+    }
+    if node.kind() == "compound_statement" {
+        return block(node, ident, ctx, fmt);
+    }
+    if let Some(def) = local_type(node) {
+        if def.has_body() {
+            return def.format(ident, fmt);
+        }
+        tabs(ident, fmt)?;
+        return match node.kind() {
+            "type_definition" => {
+                let (ty, rest) = def.single_line();
+                writeln!(fmt, "{ty}{rest}")
+            }
+            // the `;` of `union u;` isn't part of the specifier
+            _ if syntax::has_comment(node) => writeln!(fmt, "{};", text(node)),
+            _ => writeln!(fmt, "{}", def.single_line().0),
+        };
+    }
+    if node.kind() == "declaration" {
+        match ctx.hoisted.get(&node.id()) {
+            Some(true) => {
+                for line in assignments(node) {
+                    tabs(ident, fmt)?;
+                    writeln!(fmt, "{line}")?;
+                }
+                return Ok(());
+            }
+            Some(false) => return Ok(()),
+            None => (),
+        }
+    }
+    if loses_comment(node) {
+        tabs(ident, fmt)?;
+        if node.kind() == "continue_statement" {
+            for line in &ctx.continue_step {
+                writeln!(fmt, "{line}")?;
+                tabs(ident, fmt)?;
+            }
+        }
+        return writeln!(fmt, "{}", text(node));
+    }
+    if node.kind() == "for_statement"
+        && config::with(|c| c.fix_for_loops)
+        && !node
+            .child_by_field_name("body")
+            .is_some_and(continue_as_written)
+        && !step_shadowed(node)
+    {
+        return for_as_while(node, ident, ctx, fmt);
+    }
+    match node.kind() {
+        "labeled_statement" => {
+            let label = node
+                .child_by_field_name("label")
+                .map(text)
+                .unwrap_or_default();
+            let mut cursor = node.walk();
+            let (comments, inner): (Vec<_>, Vec<_>) = node
+                .named_children(&mut cursor)
+                .filter(|n| n.kind() != "statement_identifier")
+                .partition(|n| n.kind() == "comment");
+            let comments = comments
+                .into_iter()
+                .filter(|c| !ctx.hoisted.contains_key(&c.id()))
+                .map(text)
+                .collect::<Vec<_>>();
+            head(format!("{label}:"), &comments, 0, fmt)?;
+            for inner in inner {
+                statement(inner, ident, ctx, fmt)?;
+            }
+        }
+        "if_statement" => {
+            let consequence = node.child_by_field_name("consequence");
+            let (before, after) = own_comments(node, consequence, ctx);
+            head(format!("if {}", condition(node)), &before, ident, fmt)?;
+            if let Some(consequence) = consequence {
+                commented_body(consequence, ident, ctx, &after, fmt)?;
+            }
+            let mut alternative = node.child_by_field_name("alternative");
+            while let Some(alt) = alternative.take() {
+                let mut cursor = alt.walk();
+                let children = match alt.kind() {
+                    "else_clause" => alt.named_children(&mut cursor).collect(),
+                    _ => vec![alt],
+                };
+                // `else // why`: the comment comes before the body
+                let Some(&inner) = children.iter().find(|n| n.kind() != "comment") else {
+                    break;
+                };
+                let mut comments = children
+                    .iter()
+                    .filter(|n| n.kind() == "comment" && !ctx.hoisted.contains_key(&n.id()))
+                    .map(|&n| text(n))
+                    .collect::<Vec<_>>();
+                if inner.kind() == "if_statement" {
+                    let consequence = inner.child_by_field_name("consequence");
+                    let (before, after) = own_comments(inner, consequence, ctx);
+                    comments.extend(before);
+                    head(
+                        format!("else if {}", condition(inner)),
+                        &comments,
+                        ident,
+                        fmt,
+                    )?;
+                    if let Some(consequence) = consequence {
+                        commented_body(consequence, ident, ctx, &after, fmt)?;
+                    }
+                    alternative = inner.child_by_field_name("alternative");
+                } else {
+                    head("else".to_string(), &comments, ident, fmt)?;
+                    body(inner, ident, ctx, fmt)?;
+                }
+            }
+        }
+        "while_statement" => {
+            let b = node.child_by_field_name("body");
+            let (before, after) = own_comments(node, b, ctx);
+            head(format!("while {}", condition(node)), &before, ident, fmt)?;
+            if let Some(b) = b {
+                commented_body(b, ident, &ctx.in_loop(Vec::new()), &after, fmt)?;
+            }
+        }
+        "do_statement" => {
+            let b = node.child_by_field_name("body");
+            let (before, after) = own_comments(node, b, ctx);
+            head("do".to_string(), &before, ident, fmt)?;
+            // the comments after the condition end the `while` line
+            let cond = node.child_by_field_name("condition");
+            let (after, last) = after.into_iter().partition::<Vec<_>, _>(|c| {
+                cond.is_some_and(|cond| c.start_byte() < cond.start_byte())
+            });
+            if let Some(b) = b {
+                commented_body(b, ident, &ctx.in_loop(Vec::new()), &after, fmt)?;
+            }
+            let last = last.into_iter().map(text).collect::<Vec<_>>();
+            head(format!("while {};", condition(node)), &last, ident, fmt)?;
+        }
+        "for_statement" => {
+            let init = node
+                .child_by_field_name("initializer")
+                .map(|n| match n.kind() {
+                    "declaration" if ctx.hoisted.get(&n.id()) == Some(&true) => {
+                        let init = assignments(n);
+                        let init = init.iter().map(|a| a.trim_end_matches(';'));
+                        format!("{};", init.collect::<Vec<_>>().join(", "))
+                    }
+                    "declaration" => {
+                        let (ty, decls) = declaration(n);
+                        format!("{ty} {decls}")
+                    }
+                    _ => format!("{};", expression(n)),
+                })
+                .unwrap_or_else(|| ";".to_string());
+            let cond = node
+                .child_by_field_name("condition")
+                .map(|n| format!(" {}", expression(n)))
+                .unwrap_or_default();
+            let update = node
+                .child_by_field_name("update")
+                .map(|n| format!(" {}", expression(n)))
+                .unwrap_or_default();
+            let b = node.child_by_field_name("body");
+            let (before, after) = own_comments(node, b, ctx);
+            head(format!("for ({init}{cond};{update})"), &before, ident, fmt)?;
+            if let Some(b) = b {
+                commented_body(b, ident, &ctx.in_loop(Vec::new()), &after, fmt)?;
+            }
+        }
+        "switch_statement" => {
+            let b = node.child_by_field_name("body");
+            let (before, after) = own_comments(node, b, ctx);
+            head(format!("switch {}", condition(node)), &before, ident, fmt)?;
+            if let Some(b) = b {
+                commented_body(b, ident, ctx, &after, fmt)?;
+            }
+        }
+        _ => {
+            tabs(ident, fmt)?;
+            simple_statement(node, ident, ctx, fmt)?;
+        }
+    }
+    Ok(())
+}
+
+/// A type defined in a function body, printed like the ones of the file.
+fn local_type(node: Node<'_>) -> Option<TypeDefinition<'_>> {
+    match node.kind() {
+        "type_definition" | "struct_specifier" | "enum_specifier" | "union_specifier" => {
+            Some(TypeDefinition(CommentBlock::default(), node))
+        }
+        _ => None,
+    }
+}
+
+/// Prints a statement without a body, its indentation already written.
+fn simple_statement(
+    node: Node<'_>,
+    ident: usize,
+    ctx: &Context,
+    fmt: &mut impl Write,
+) -> std::io::Result<()> {
+    match node.kind() {
+        "expression_statement" => {
+            let expr = node
+                .named_child(0)
+                .filter(|n| n.kind() != "comment")
+                .map(expression)
+                .unwrap_or_default();
+            writeln!(fmt, "{expr};")
+        }
+        "declaration" => {
+            let (ty, decls) = declaration(node);
+            writeln!(fmt, "{ty}\t{decls}")
+        }
+        "return_statement" => match node.named_child(0).filter(|n| n.kind() != "comment") {
+            Some(value) if value.kind() == "parenthesized_expression" => {
+                writeln!(fmt, "return {};", expression(value))
+            }
+            Some(value) => writeln!(fmt, "return ({});", expression(value)),
+            None => writeln!(fmt, "return ;"),
+        },
+        "break_statement" => writeln!(fmt, "break;"),
+        "continue_statement" => {
+            for line in &ctx.continue_step {
+                writeln!(fmt, "{line}")?;
+                tabs(ident, fmt)?;
+            }
+            writeln!(fmt, "continue;")
+        }
+        "goto_statement" => writeln!(
+            fmt,
+            "goto {};",
+            node.child_by_field_name("label")
+                .map(text)
+                .unwrap_or_default()
+        ),
+        "case_statement" => {
+            match node.child_by_field_name("value") {
+                Some(value) => writeln!(fmt, "case {}:", expression(value))?,
+                None => writeln!(fmt, "default:")?,
+            }
+            let mut cursor = node.walk();
+            let children = node
+                .named_children(&mut cursor)
+                .filter(|&child| Some(child) != node.child_by_field_name("value"))
+                .collect();
+            statement_list(children, ident + 1, ctx, fmt)
+        }
+        _ => writeln!(fmt, "{}", collapsed(node)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+
+    /// Formats `source` without a header, with the command line `flags`.
+    fn format(source: &str, flags: &[&str]) -> String {
+        let mut config = config::Config::default();
+        for flag in ["--header=off"].iter().chain(flags) {
+            config.apply_flag(flag).unwrap();
+        }
+        config::set(config);
+        let output = crate::run("test.c", source.as_bytes().into(), Vec::new())
+            .unwrap()
+            .0;
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn shadowing_declarations_stay_in_their_block() {
+        let source = "int\tf(int c)\n{\n\tint x = 5;\n\tif (c)\n\t{\n\t\tint x = 1;\n\t\tg(x);\n\t}\n\treturn (x);\n}\n";
+        assert_eq!(
+            format(source, &["--fix-declarations"]),
+            "int\t\tf(int c)\n{\n\tint\tx;\n\n\tx = 5;\n\tif (c)\n\t{\n\t\tint\tx = 1;\n\t\tg(x);\n\t}\n\treturn (x);\n}\n"
+        );
+    }
+
+    #[test]
+    fn shadowing_for_declarations_stay_in_their_loop() {
+        let source = "int\tf(int n)\n{\n\tint i = 5;\n\tfor (int i = 0; i < n; i++)\n\t\tg(i);\n\treturn (i);\n}\n";
+        assert_eq!(
+            format(source, &["--fix-declarations"]),
+            "int\t\tf(int n)\n{\n\tint\ti;\n\n\ti = 5;\n\tfor (int i = 0; i < n; i++)\n\t\tg(i);\n\treturn (i);\n}\n"
+        );
+    }
+
+    #[test]
+    fn declarations_of_separate_blocks_are_merged() {
+        let source = "void\tf(int n)\n{\n\tif (n)\n\t{\n\t\tint y = 2;\n\t\tg(y);\n\t}\n\tint y = 3;\n\tg(y);\n}\n";
+        assert_eq!(
+            format(source, &["--fix-declarations"]),
+            "void\tf(int n)\n{\n\tint\ty;\n\n\tif (n)\n\t{\n\t\ty = 2;\n\t\tg(y);\n\t}\n\ty = 3;\n\tg(y);\n}\n"
+        );
+    }
+
+    #[test]
+    fn declarations_hiding_a_global_stay_in_their_block() {
+        let source = "void\tf(void)\n{\n\tg(z);\n\t{\n\t\tint z = 1;\n\t\tg(z);\n\t}\n}\n";
+        assert_eq!(
+            format(source, &["--fix-declarations"]),
+            "void\tf(void)\n{\n\tg(z);\n\t{\n\t\tint\tz = 1;\n\t\tg(z);\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn declarations_staying_in_the_body_are_not_moved_twice() {
+        let source = "void\tf(int n)\n{\n\tif (n)\n\t{\n\t\tint y = 2;\n\t\tg(y);\n\t}\n\tstatic int y = 3;\n\tg(y);\n}\n";
+        assert_eq!(
+            format(source, &["--fix-declarations"]),
+            "void\tf(int n)\n{\n\tif (n)\n\t{\n\t\tint\ty = 2;\n\t\tg(y);\n\t}\n\tstatic int\ty = 3;\n\tg(y);\n}\n"
+        );
+    }
+
+    #[test]
+    fn for_loops_run_their_step_before_continue() {
+        let source = "void\tf(int n)\n{\n\tint\ti;\n\tint\tj;\n\n\tfor (i = 0, j = n; i < j; i++, j--)\n\t{\n\t\tif (i == 2)\n\t\t\tcontinue;\n\t\tg(i, j);\n\t}\n}\n";
+        assert_eq!(
+            format(source, &["--fix-for-loops"]),
+            "void\tf(int n)\n{\n\tint\ti;\n\tint\tj;\n\n\ti = 0;\n\tj = n;\n\twhile (i < j)\n\t{\n\t\tif (i == 2)\n\t\t{\n\t\t\ti++;\n\t\t\tj--;\n\t\t\tcontinue;\n\t\t}\n\t\tg(i, j);\n\t\ti++;\n\t\tj--;\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn for_loops_declarations_are_hoisted() {
+        let source = "void\tf(int n)\n{\n\tfor (int i = 0; i < n; i++)\n\t\tg(i);\n}\n";
+        assert_eq!(
+            format(source, &["--fix-for-loops"]),
+            "void\tf(int n)\n{\n\tint\ti;\n\n\ti = 0;\n\twhile (i < n)\n\t{\n\t\tg(i);\n\t\ti++;\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn for_loops_shadowing_a_variable_get_their_own_block() {
+        let source = "int\tf(int n)\n{\n\tint i = 5;\n\tfor (int i = 0; i < n; i++)\n\t\tg(i);\n\treturn (i);\n}\n";
+        assert_eq!(
+            format(source, &["--fix-for-loops"]),
+            "int\t\tf(int n)\n{\n\tint\ti = 5;\n\n\t{\n\t\tint\ti = 0;\n\t\twhile (i < n)\n\t\t{\n\t\t\tg(i);\n\t\t\ti++;\n\t\t}\n\t}\n\treturn (i);\n}\n"
+        );
+    }
+
+    #[test]
+    fn local_types_keep_their_semicolon() {
+        let source = "void\tf(void)\n{\n\tenum { A = 1 };\n\tunion u;\n\tg(A);\n}\n";
+        assert_eq!(
+            format(source, &[]),
+            "void\tf(void)\n{\n\tenum\n\t{\n\t\tA = 1\n\t};\n\tunion u;\n\tg(A);\n}\n"
+        );
+    }
+
+    #[test]
+    fn for_loops_whose_body_shadows_the_step_stay() {
+        let source = "void\tf(int n)\n{\n\tint\ti;\n\n\tfor (i = 0; i < n; i++)\n\t{\n\t\tint\ti = 7;\n\t\tg(i);\n\t}\n}\n";
+        assert_eq!(format(source, &["--fix-for-loops"]), source);
+    }
+
+    #[test]
+    fn variable_length_arrays_stay_below_their_size() {
+        let source = "void\tf(int m)\n{\n\tint\tn;\n\n\tn = m * 2;\n\tint\tarr[n];\n\tg(arr);\n}\n";
+        assert_eq!(format(source, &["--fix-declarations"]), source);
+        let source = "void\tf(int m)\n{\n\tint\ta = m;\n\tint\tb[a];\n\n\tg(b);\n}\n";
+        assert_eq!(format(source, &["--fix-declarations"]), source);
+    }
+
+    #[test]
+    fn declarations_stay_below_their_local_type() {
+        let source = "void\tf(void)\n{\n\tg();\n\ttypedef int\tt_e;\n\tt_e\te;\n\tg(&e);\n}\n";
+        assert_eq!(format(source, &["--fix-declarations"]), source);
+    }
+
+    #[test]
+    fn sizeof_of_a_type_in_an_operation() {
+        let source =
+            "void\tf(void)\n{\n\tp = malloc(sizeof(char) * len + 1);\n\tq = sizeof(t_x) + 1;\n}\n";
+        assert_eq!(format(source, &[]), source);
+    }
+
+    #[test]
+    fn comments_of_control_statements_are_moved() {
+        let source =
+            "void\tf(int a)\n{\n\tif (a) // check\n\t\tg();\n\telse // otherwise\n\t\th();\n}\n";
+        let expected = "//\tcheck\n//\t(in `if (a)`)\n//\totherwise\n//\t(in `else`)\nvoid\tf(int a)\n{\n\tif (a)\n\t\tg();\n\telse\n\t\th();\n}\n";
+        assert_eq!(format(source, &["--fix-comments"]), expected);
+    }
+}
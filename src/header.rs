@@ -1,8 +1,9 @@
 use chrono::Local;
 use std::collections::HashMap;
-use std::env::var;
 use std::sync::LazyLock;
 
+use crate::config::{self, HeaderPolicy};
+
 static ART: [&str; 7] = [
     "        :::      ::::::::",
     "      :+:      :+:    :+:",
@@ -44,12 +45,19 @@ static TYPES: LazyLock<HashMap<&'static str, [&'static str; 3]>> = LazyLock::new
     m
 });
 
+/// The width of the header and the columns from the start of a line to its
+/// text.
+fn dimensions() -> (usize, usize) {
+    config::with(|c| (c.header_width, c.header_margin))
+}
+
 fn make_top_bottom_lines(header: &mut [String; 11], start: &str, end: &str, fill: &str) {
+    let (len, _) = dimensions();
     let end_idx = header.len() - 1;
     let mut fill_row = |row: usize| {
         header[row].push_str(start);
         header[row].push(' ');
-        for _ in 0..(LEN - start.len() - end.len() - 2) {
+        for _ in 0..len.saturating_sub(start.len() + end.len() + 2) {
             header[row].push_str(fill);
         }
         header[row].push(' ');
@@ -69,39 +77,56 @@ fn make_top_bottom_lines(header: &mut [String; 11], start: &str, end: &str, fill
 // . s:end
 
 fn text_line(line: &mut String, left: &str, right: &str, (start, end): (&str, &str)) {
+    let (len, margin) = dimensions();
+    let text_len = len.saturating_sub(margin * 2);
     let l = &left[0..(left
         .char_indices()
-        .nth(LEN - MARGIN * 2 - right.len())
+        .nth(text_len.saturating_sub(right.len()))
         .map(|(i, _)| i)
-        .unwrap_or(left.as_bytes().len()))];
+        .unwrap_or(left.len()))];
     line.push_str(start);
-    for _ in 0..(MARGIN - start.len()) {
+    for _ in 0..margin.saturating_sub(start.len()) {
         line.push(' ');
     }
     line.push_str(l);
-    for _ in 0..(LEN - MARGIN * 2 - l.len() - right.len()) {
+    for _ in 0..text_len.saturating_sub(l.len() + right.len()) {
         line.push(' ');
     }
     line.push_str(right);
-    for _ in 0..(MARGIN - end.len()) {
+    for _ in 0..margin.saturating_sub(end.len()) {
         line.push(' ');
     }
     line.push_str(end);
 }
 
-const LEN: usize = 80;
-const MARGIN: usize = 5;
-
 #[allow(dead_code, unused)]
 pub fn insert_header(
     filename: &str,
     output: &mut impl std::io::Write,
     current_header: Option<[String; 11]>,
 ) -> std::io::Result<()> {
-    let user = var("USER").ok().unwrap_or_else(|| "marvin".to_string());
-    let mail = var("MAIL")
-        .ok()
-        .unwrap_or_else(|| "marvin@42.fr".to_string());
+    let (policy, user, mail) = config::with(|c| (c.header, c.author(), c.mail()));
+    match (policy, current_header) {
+        (HeaderPolicy::Update, current_header) => {
+            update_header(filename, output, current_header, (&user, &mail))
+        }
+        (_, Some(header)) => header
+            .iter()
+            .try_for_each(|line| writeln!(output, "{line}")),
+        (HeaderPolicy::Keep, None) => update_header(filename, output, None, (&user, &mail)),
+        (HeaderPolicy::Off, None) => Ok(()),
+    }
+}
+
+/// Writes `current_header` with a refreshed `Updated:` line, or a new header
+/// if there is none.
+fn update_header(
+    filename: &str,
+    output: &mut impl std::io::Write,
+    current_header: Option<[String; 11]>,
+    (user, mail): (&str, &str),
+) -> std::io::Result<()> {
+    let (len, _) = dimensions();
     let time = Local::now().format("%Y/%m/%d %H:%M:%S");
     let [s, e, m] = TYPES
         .iter()
@@ -110,7 +135,7 @@ pub fn insert_header(
         .next()
         .unwrap_or(["#", "#", "*"]);
     let mut header = current_header.unwrap_or_else(|| {
-        let mut out: [String; 11] = std::array::from_fn(|_| String::with_capacity(LEN + 1));
+        let mut out: [String; 11] = std::array::from_fn(|_| String::with_capacity(len + 1));
         make_top_bottom_lines(&mut out, s, e, m);
 
         // BLANK LINE
@@ -180,3 +205,17 @@ pub fn insert_header(
 
     Ok(())
 }
+
+/// Whether two versions of a file are the same but for the `Updated:` line of
+/// their header, which changes every time the file is formatted.
+pub fn same_but_updated(a: &[u8], b: &[u8]) -> bool {
+    const UPDATED_ROW: usize = 8;
+    let updated = |line: &[u8]| line.windows(9).any(|w| w == b"Updated: ");
+    let a = a.split(|&c| c == b'\n').collect::<Vec<_>>();
+    let b = b.split(|&c| c == b'\n').collect::<Vec<_>>();
+    a.len() == b.len()
+        && a.iter()
+            .zip(&b)
+            .enumerate()
+            .all(|(row, (a, b))| a == b || (row == UPDATED_ROW && updated(a) && updated(b)))
+}
@@ -0,0 +1,307 @@
+//! Norm checker: reports the violations of a file instead of rewriting it.
+
+use tree_sitter::Node;
+
+use crate::{
+    config, get_data, max_columns, tab_width, tabbed_len, FunctionDefinition, TopLevelBlock,
+    ToplevelDefinition,
+};
+
+const MAX_FUNCTION_LINES: usize = 25;
+const MAX_FUNCTIONS: usize = 5;
+const MAX_PARAMETERS: usize = 4;
+const MAX_VARIABLES: usize = 5;
+
+/// Every rule reported, which can be disabled one by one.
+pub const RULES: &[&str] = &[
+    "LINE_TOO_LONG",
+    "TOO_MANY_FUNCS",
+    "TOO_MANY_LINES",
+    "TOO_MANY_ARGS",
+    "TOO_MANY_VARS_FUNC",
+    "FORBIDDEN_FOR",
+    "FORBIDDEN_DO_WHILE",
+    "FORBIDDEN_SWITCH",
+    "FORBIDDEN_CASE",
+    "FORBIDDEN_GOTO",
+    "FORBIDDEN_TERNARY",
+];
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// 0-based, like tree-sitter positions
+    pub row: usize,
+    /// 0-based, counted in rendered columns
+    pub column: usize,
+    /// End of the offending code, exclusive
+    pub end_row: usize,
+    pub end_column: usize,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl Violation {
+    fn at(node: Node<'_>, rule: &'static str, message: String) -> Self {
+        Self::span(node, node, rule, message)
+    }
+
+    /// A violation going from the start of `start` to the end of `end`.
+    fn span(start: Node<'_>, end: Node<'_>, rule: &'static str, message: String) -> Self {
+        let (start, end) = (start.start_position(), end.end_position());
+        Self {
+            row: start.row,
+            column: rendered_column(start.row, start.column),
+            end_row: end.row,
+            end_column: rendered_column(end.row, end.column),
+            rule,
+            message,
+        }
+    }
+
+    /// The offending source line, tabs expanded, with the span underlined.
+    pub fn snippet(&self) -> String {
+        let data = String::from_utf8_lossy(get_data(&()));
+        let line = data.lines().nth(self.row).unwrap_or_default();
+        let mut expanded = String::with_capacity(line.len());
+        for chr in line.chars() {
+            match chr {
+                '\t' => {
+                    let tab = tab_width();
+                    expanded.push_str(&" ".repeat(tab - tabbed_len(&expanded) % tab))
+                }
+                chr => expanded.push(chr),
+            }
+        }
+        let end = match self.end_row == self.row {
+            true => self.end_column,
+            false => tabbed_len(&expanded),
+        };
+        let width = end.saturating_sub(self.column).max(1);
+        format!(
+            "{expanded}\n{}{}",
+            " ".repeat(self.column),
+            "^".repeat(width)
+        )
+    }
+}
+
+/// Converts a byte column into the column it is displayed at, tabs being
+/// expanded.
+fn rendered_column(row: usize, byte_column: usize) -> usize {
+    let line = get_data(&())
+        .split(|&b| b == b'\n')
+        .nth(row)
+        .unwrap_or_default();
+    let line = &line[..byte_column.min(line.len())];
+    tabbed_len(&String::from_utf8_lossy(line))
+}
+
+/// Every function definition of the file, whatever the conditional block
+/// it is in.
+fn functions<'a, 'ts>(
+    blocks: &'a [TopLevelBlock<'ts>],
+    out: &mut Vec<&'a FunctionDefinition<'ts>>,
+) {
+    for block in blocks {
+        match block {
+            TopLevelBlock::PreprocIf(if_data, def) => {
+                functions(&if_data.tlb, out);
+                out.extend(&def.functions.0);
+                if let Some(alternative) = &if_data.alternative {
+                    functions(std::slice::from_ref(alternative), out);
+                }
+            }
+            TopLevelBlock::Plain(ToplevelDefinition { functions: f, .. }) => out.extend(&f.0),
+            TopLevelBlock::Error(_) => (),
+        }
+    }
+}
+
+fn line_length(out: &mut Vec<Violation>) {
+    let data = String::from_utf8_lossy(get_data(&()));
+    let max = max_columns();
+    for (row, line) in data.lines().enumerate() {
+        let len = tabbed_len(line);
+        if len > max {
+            out.push(Violation {
+                row,
+                column: max,
+                end_row: row,
+                end_column: len,
+                rule: "LINE_TOO_LONG",
+                message: format!("line is {len} columns long, the limit is {max}"),
+            });
+        }
+    }
+}
+
+fn function_count(defs: &[&FunctionDefinition<'_>], out: &mut Vec<Violation>) {
+    let mut defs = defs.iter().map(|def| def.1).collect::<Vec<_>>();
+    defs.sort_by_key(|n| n.start_byte());
+    for def in defs.into_iter().skip(MAX_FUNCTIONS) {
+        out.push(Violation::at(
+            def,
+            "TOO_MANY_FUNCS",
+            format!("more than {MAX_FUNCTIONS} functions in the file"),
+        ));
+    }
+}
+
+fn function_length(def: &FunctionDefinition<'_>, out: &mut Vec<Violation>) {
+    let Some(body) = def.1.child_by_field_name("body") else {
+        return;
+    };
+    let lines = (body.end_position().row - body.start_position().row).saturating_sub(1);
+    if lines > MAX_FUNCTION_LINES {
+        out.push(Violation::at(
+            def.1,
+            "TOO_MANY_LINES",
+            format!("function body is {lines} lines long, the limit is {MAX_FUNCTION_LINES}"),
+        ));
+    }
+}
+
+fn parameter_count(def: &FunctionDefinition<'_>, out: &mut Vec<Violation>) {
+    let mut declarator = def.1.child_by_field_name("declarator");
+    while let Some(node) = declarator.filter(|n| n.kind() != "function_declarator") {
+        declarator = node.child_by_field_name("declarator");
+    }
+    let Some(params) = declarator.and_then(|d| d.child_by_field_name("parameters")) else {
+        return;
+    };
+    let mut cursor = params.walk();
+    let count = params
+        .named_children(&mut cursor)
+        .filter(|p| p.kind() != "comment")
+        // `f(void)` takes no parameter
+        .filter(|p| {
+            p.kind() != "parameter_declaration"
+                || p.child_by_field_name("declarator").is_some()
+                || p.utf8_text(get_data(&())).unwrap().trim() != "void"
+        })
+        .count();
+    if count > MAX_PARAMETERS {
+        out.push(Violation::at(
+            params,
+            "TOO_MANY_ARGS",
+            format!("function takes {count} parameters, the limit is {MAX_PARAMETERS}"),
+        ));
+    }
+}
+
+fn variable_count(def: &FunctionDefinition<'_>, out: &mut Vec<Violation>) {
+    fn collect<'ts>(node: Node<'ts>, out: &mut Vec<Node<'ts>>) {
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            if child.kind() == "declaration" {
+                out.extend(crate::syntax::declarators(child));
+            } else {
+                collect(child, out);
+            }
+        }
+    }
+    let Some(body) = def.1.child_by_field_name("body") else {
+        return;
+    };
+    let mut variables = Vec::new();
+    collect(body, &mut variables);
+    if let Some(&extra) = variables.get(MAX_VARIABLES) {
+        out.push(Violation::at(
+            extra,
+            "TOO_MANY_VARS_FUNC",
+            format!(
+                "function declares {} variables, the limit is {MAX_VARIABLES}",
+                variables.len()
+            ),
+        ));
+    }
+}
+
+/// Control structures the Norm forbids: node kind, rule id and explanation.
+const FORBIDDEN: &[(&str, &str, &str)] = &[
+    (
+        "for_statement",
+        "FORBIDDEN_FOR",
+        "`for` loops are forbidden, use a `while` loop",
+    ),
+    (
+        "do_statement",
+        "FORBIDDEN_DO_WHILE",
+        "`do ... while` loops are forbidden, use a `while` loop",
+    ),
+    (
+        "switch_statement",
+        "FORBIDDEN_SWITCH",
+        "`switch` is forbidden, use `if` / `else if` chains",
+    ),
+    (
+        "case_statement",
+        "FORBIDDEN_CASE",
+        "`case` and `default` labels are forbidden, they only exist in a `switch`",
+    ),
+    (
+        "goto_statement",
+        "FORBIDDEN_GOTO",
+        "`goto` is forbidden, restructure the code with loops and functions",
+    ),
+    (
+        "conditional_expression",
+        "FORBIDDEN_TERNARY",
+        "the ternary operator `?:` is forbidden, use an `if` / `else`",
+    ),
+];
+
+/// The node ending the "header" of a construct, so that the span of a `for`
+/// covers `for (...)` and not its whole body.
+fn header_end(node: Node<'_>) -> Node<'_> {
+    let mut cursor = node.walk();
+    let end = match node.kind() {
+        "for_statement" => node.children(&mut cursor).find(|n| n.kind() == ")"),
+        "switch_statement" => node.child_by_field_name("condition"),
+        "case_statement" => node.children(&mut cursor).find(|n| n.kind() == ":"),
+        "do_statement" => node.child(0),
+        _ => None,
+    };
+    end.unwrap_or(node)
+}
+
+fn forbidden_constructs(def: &FunctionDefinition<'_>, out: &mut Vec<Violation>) {
+    fn walk(node: Node<'_>, out: &mut Vec<Violation>) {
+        if let Some((_, rule, explanation)) = FORBIDDEN.iter().find(|(k, ..)| *k == node.kind()) {
+            out.push(Violation::span(
+                node,
+                header_end(node),
+                rule,
+                explanation.to_string(),
+            ));
+        }
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            walk(child, out);
+        }
+    }
+    if let Some(body) = def.1.child_by_field_name("body") {
+        walk(body, out);
+    }
+}
+
+/// Checks the file currently loaded in `CURRENT_DATA_BYTES`, whose parsed
+/// form is `top_level`. Violations are sorted by position, the disabled
+/// rules being left out.
+pub fn lint(top_level: &[TopLevelBlock<'_>]) -> Vec<Violation> {
+    let mut out = Vec::new();
+    let mut defs = Vec::new();
+    functions(top_level, &mut defs);
+
+    line_length(&mut out);
+    function_count(&defs, &mut out);
+    for def in &defs {
+        function_length(def, &mut out);
+        parameter_count(def, &mut out);
+        variable_count(def, &mut out);
+        forbidden_constructs(def, &mut out);
+    }
+    config::with(|c| out.retain(|v| !c.disabled_rules.contains(v.rule)));
+    out.sort_by_key(|v| (v.row, v.column));
+    out
+}
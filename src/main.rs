@@ -1,99 +1,306 @@
 #![allow(dead_code)]
-#![feature(lazy_cell)]
-use std::io::Read;
 use std::{collections::VecDeque, mem::MaybeUninit};
 
 use std::cell::RefCell;
 
+mod config;
+mod diff;
+mod files;
+mod function;
 mod header;
+mod lint;
+mod syntax;
+mod types;
 
 thread_local! {
     pub static CURRENT_DATA_BYTES: RefCell<Box<[u8]>> = RefCell::new(Vec::new().into_boxed_slice());
 }
-use tree_sitter::{Node, TextProvider, Tree};
+use tree_sitter::{Node, Tree};
+
+/// The maximum line length of the current file, 80 for the Norm.
+fn max_columns() -> usize {
+    config::with(|c| c.line_width)
+}
+
+/// The columns a tab advances to a multiple of, 4 for the Norm.
+fn tab_width() -> usize {
+    config::with(|c| c.tab_width)
+}
 
 const TEST_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/", "sample/", "1.c");
 
+/// What is done with the formatted files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Print them on stdout.
+    Print,
+    /// Report the Norm violations of the input instead of formatting it.
+    Lint,
+    /// Write them back in place of their input.
+    InPlace,
+    /// List the files that aren't formatted, without writing anything.
+    Check,
+    /// Print what formatting would change as a unified diff.
+    Diff,
+}
+
 fn main() {
-    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
-    if args.is_empty() {
-        let mut buffer = Vec::with_capacity(1024);
-        std::io::stdin().lock().read_to_end(&mut buffer).unwrap();
-        run(
-            "new_file.c",
-            buffer.into_boxed_slice(),
-            Vec::with_capacity(1024),
-        );
-        return;
-    }
-    args.iter()
-        .map(|o| {
-            std::fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .open(o)
-                .map(|v| (o, v))
-        })
-        .map(|res| res.map(|(p, f)| f.metadata().map(|m| (p, f, m))))
-        .map(|res| match res {
-            Err(e) | Ok(Err(e)) => Err(e),
-            Ok(Ok(v)) => Ok(v),
-        })
-        .map(|res| {
-            res.map(|(path, file, metadata)| {
-                (
-                    path,
-                    file,
-                    Vec::<u8>::with_capacity(metadata.len() as usize),
-                )
-            })
-        })
-        .map(|res| -> Result<_, std::io::Error> {
-            let (path, mut f, o) = res?;
-            let mut buffer = Vec::with_capacity(o.len());
-            f.read_to_end(&mut buffer)?;
-            Ok((path, f, buffer, o))
-        })
-        .map(|res| {
-            res.map(|(p, f, i, o)| {
-                (
-                    p,
-                    f,
-                    run(
-                        std::path::PathBuf::from(&p)
-                            .file_name()
-                            .and_then(std::ffi::OsStr::to_str)
-                            .unwrap_or("<new file>"),
-                        i.into_boxed_slice(),
-                        o,
-                    ),
-                )
-            })
-        })
-        .for_each(|res| match res {
-            Ok((path, _f, o)) => {
+    let mut flags = Vec::new();
+    let mut args = Vec::new();
+    let mut mode = Mode::Print;
+    let mut filter = files::Filter::default();
+    let mut stdin_path = None;
+    let mut argv = std::env::args_os().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.to_str() {
+            Some("--lint") => mode = Mode::Lint,
+            Some("--in-place" | "-i") => mode = Mode::InPlace,
+            Some("--check") => mode = Mode::Check,
+            Some("--diff") => mode = Mode::Diff,
+            Some(flag @ ("--include" | "--exclude")) => {
+                let Some(glob) = argv.next().and_then(|g| g.into_string().ok()) else {
+                    eprintln!("Error: `{flag}` needs a glob");
+                    std::process::exit(2);
+                };
+                match flag {
+                    "--include" => filter.include.push(glob),
+                    _ => filter.exclude.push(glob),
+                }
+            }
+            Some(flag) if flag.starts_with("--include=") => {
+                filter.include.push(flag["--include=".len()..].to_string())
+            }
+            Some(flag) if flag.starts_with("--exclude=") => {
+                filter.exclude.push(flag["--exclude=".len()..].to_string())
+            }
+            Some("--stdin-filepath") => {
+                let Some(path) = argv.next() else {
+                    eprintln!("Error: `--stdin-filepath` needs a path");
+                    std::process::exit(2);
+                };
+                stdin_path = Some(std::path::PathBuf::from(path));
+            }
+            Some(flag) if flag.starts_with("--stdin-filepath=") => {
+                stdin_path = Some(flag["--stdin-filepath=".len()..].into())
+            }
+            Some(flag) if config::VALUE_FLAGS.contains(&flag) => {
+                let Some(value) = argv.next().and_then(|v| v.into_string().ok()) else {
+                    eprintln!("Error: `{flag}` needs a value");
+                    std::process::exit(2);
+                };
+                flags.push(format!("{flag}={value}"));
+            }
+            Some(flag) if flag.starts_with("--") => flags.push(flag.to_string()),
+            _ => args.push(arg),
+        }
+    }
+    // the flags are applied again over the configuration file of each input
+    let mut config = config::Config::default();
+    if let Err(e) = flags.iter().try_for_each(|flag| config.apply_flag(flag)) {
+        eprintln!("Error: {e}");
+        std::process::exit(2);
+    }
+    if let Err(e) = filter.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(2);
+    }
+    if args.is_empty() && mode == Mode::InPlace {
+        eprintln!("Error: --in-place needs files to write to");
+        std::process::exit(2);
+    }
+    let inputs = files::inputs(&args, &filter, &flags, stdin_path.as_deref());
+    std::process::exit(match mode {
+        Mode::Lint => lint_files(inputs),
+        _ => format_files(inputs, mode),
+    });
+}
+
+/// Formats every input, printing the result, writing it back or checking
+/// it depending on `mode`, and returns the exit status: 1 if a file couldn't
+/// be read or formatted or, with `--check`, isn't formatted.
+fn format_files(inputs: impl Iterator<Item = Result<files::Input, String>>, mode: Mode) -> i32 {
+    let mut status = 0;
+    for input in inputs {
+        let files::Input {
+            path,
+            filename,
+            data,
+            config,
+        } = match input {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                status = 1;
+                continue;
+            }
+        };
+        config::set(config);
+        let output = Vec::with_capacity(data.len());
+        let output = match run(&filename, data.clone().into_boxed_slice(), output) {
+            Ok((output, warnings)) => {
+                for warning in warnings {
+                    eprintln!("Warning: {}: {warning}", path.display());
+                }
+                output
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {e}", path.display());
+                status = 1;
+                continue;
+            }
+        };
+        let result = match mode {
+            Mode::InPlace | Mode::Check | Mode::Diff
+                if header::same_but_updated(&data, &output) =>
+            {
+                Ok(())
+            }
+            Mode::InPlace => files::write_in_place(&path, &output),
+            Mode::Check => {
+                println!("{}", path.display());
+                status = 1;
+                Ok(())
+            }
+            Mode::Diff => {
+                use std::io::IsTerminal;
+                let color =
+                    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
                 print!(
-                    //"//{}:\n{:}", "{}"
-                    //std::path::PathBuf::from(path).display(),
                     "{}",
-                    std::str::from_utf8(&o).unwrap()
+                    diff::unified(
+                        &path.display().to_string(),
+                        std::str::from_utf8(&data).unwrap(),
+                        std::str::from_utf8(&output).unwrap(),
+                        color
+                    )
                 );
+                Ok(())
+            }
+            _ => {
+                print!("{}", std::str::from_utf8(&output).unwrap());
+                Ok(())
             }
+        };
+        if let Err(e) = result {
+            eprintln!("Error: {}: {e}", path.display());
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Reports the Norm violations of every input, returning the exit status: 1
+/// if anything was reported or a file couldn't be read.
+fn lint_files(inputs: impl Iterator<Item = Result<files::Input, String>>) -> i32 {
+    let mut status = 0;
+    for input in inputs {
+        let files::Input {
+            path, data, config, ..
+        } = match input {
+            Ok(input) => input,
             Err(e) => {
-                println!("Error: {e}");
+                eprintln!("Error: {e}");
+                status = 1;
+                continue;
             }
-        });
+        };
+        config::set(config);
+        let Some(tree) = parse(data.into_boxed_slice()) else {
+            eprintln!("Error: {}: not valid UTF-8", path.display());
+            status = 1;
+            continue;
+        };
+        let top_level = ToplevelDefinition::from_tree(&tree);
+        if let Err(e) = unsupported(&tree, &top_level) {
+            eprintln!("Error: {}: {e}", path.display());
+            status = 1;
+            continue;
+        }
+        for v in lint::lint(&top_level) {
+            status = 1;
+            println!(
+                "{}:{}:{}: {}: {}\n{}",
+                path.display(),
+                v.row + 1,
+                v.column + 1,
+                v.rule,
+                v.message,
+                v.snippet()
+            );
+        }
+    }
+    status
 }
 
-fn run(filename: &str, data: Box<[u8]>, mut output: Vec<u8>) -> Vec<u8> {
+/// Loads `data` as the current file and parses it, `None` if it isn't valid
+/// UTF-8.
+fn parse(data: Box<[u8]>) -> Option<Tree> {
     let mut ts = tree_sitter::Parser::new();
     ts.set_language(tree_sitter_c::language()).unwrap();
     if std::str::from_utf8(&data).is_err() {
-        return Vec::new();
+        return None;
     }
     CURRENT_DATA_BYTES.set(data);
-    let tree = ts.parse(get_data(&()), None).unwrap();
+    ts.parse(get_data(&()), None)
+}
+
+/// Fails on the first part of the file that can't be printed back as it is
+/// meant: a syntax error or a top-level construct the formatter knows
+/// nothing about.
+fn unsupported(tree: &Tree, top_level: &[TopLevelBlock<'_>]) -> Result<(), String> {
+    fn first_error(node: Node<'_>) -> Option<Node<'_>> {
+        if node.is_error() || node.is_missing() {
+            return Some(node);
+        }
+        let mut cursor = node.walk();
+        let children = node.children(&mut cursor).collect::<Vec<_>>();
+        children
+            .into_iter()
+            .filter(Node::has_error)
+            .find_map(first_error)
+    }
+    fn unknown<'ts>(blocks: &[TopLevelBlock<'ts>]) -> Option<Node<'ts>> {
+        blocks.iter().find_map(|block| match block {
+            TopLevelBlock::Error(node) => Some(*node),
+            TopLevelBlock::PreprocIf(if_data, _) => unknown(&if_data.tlb)
+                .or_else(|| unknown(std::slice::from_ref(if_data.alternative.as_deref()?))),
+            TopLevelBlock::Plain(_) => None,
+        })
+    }
+    let root = tree.root_node();
+    if let Some(node) = root.has_error().then(|| first_error(root)).flatten() {
+        return Err(format!(
+            "line {}: syntax error",
+            node.start_position().row + 1
+        ));
+    }
+    match unknown(top_level) {
+        Some(node) => Err(format!(
+            "line {}: `{}` is not supported",
+            node.start_position().row + 1,
+            syntax::text(node).lines().next().unwrap_or_default().trim()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Formats `data`, the content of `filename`, appending the result to
+/// `output`, along with warnings about what was asked but couldn't be done.
+/// Fails, with nothing formatted, if it isn't valid UTF-8 or holds something
+/// that would be lost in the process.
+fn run(
+    filename: &str,
+    data: Box<[u8]>,
+    mut output: Vec<u8>,
+) -> Result<(Vec<u8>, Vec<String>), String> {
+    let tree = parse(data).ok_or("not valid UTF-8")?;
     let top_level = ToplevelDefinition::from_tree(&tree);
+    unsupported(&tree, &top_level)?;
+    let column = match config::with(|c| c.align_file) {
+        true => signature_column(&top_level),
+        false => 0,
+    };
+    config::update(|c| c.signature_column = column);
 
     if let Some(TopLevelBlock::Plain(ToplevelDefinition { header, .. })) = top_level.last() {
         header::insert_header(
@@ -109,11 +316,33 @@ fn run(filename: &str, data: Box<[u8]>, mut output: Vec<u8>) -> Vec<u8> {
         .unwrap();
     }
 
-    top_level
-        .iter()
-        .map(|t| t.format(filename, 0, &mut output))
-        .for_each(|r| r.unwrap());
-    output
+    let mut body = Vec::with_capacity(output.capacity());
+    match top_level.split_last() {
+        Some((TopLevelBlock::Plain(def), nested)) => def.format(filename, 0, nested, &mut body),
+        _ => top_level
+            .iter()
+            .try_for_each(|t| t.format(filename, 0, &mut body)),
+    }
+    .unwrap();
+    if !output.is_empty() && !body.is_empty() {
+        output.push(b'\n');
+    }
+    output.extend(body);
+
+    let mut warnings = Vec::new();
+    if config::with(|c| c.fix_comments) {
+        warnings.extend(
+            function::kept_comments(tree.root_node())
+                .into_iter()
+                .map(|comment| {
+                    format!(
+                        "line {}: comment inside a statement kept as written, not moved",
+                        comment.start_position().row + 1
+                    )
+                }),
+        );
+    }
+    Ok((output, warnings))
 }
 
 #[derive(Debug, Clone)]
@@ -126,9 +355,13 @@ enum TopLevelBlock<'ts> {
 #[derive(Debug, Clone)]
 struct PreprocIfData<'ts> {
     node: Node<'ts>,
+    /// The comments written above the `#if`, which move along with it
+    comments: CommentBlock<'ts>,
     ifnode: Option<Node<'ts>>,
     ifnode_identifier: Option<Node<'ts>>,
     tlb: Vec<TopLevelBlock<'ts>>,
+    /// The `#elif`/`#else` branch following this one, itself a `PreprocIf`
+    alternative: Option<Box<TopLevelBlock<'ts>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -138,11 +371,52 @@ struct ToplevelDefinition<'ts> {
     declarations: DeclarationBlock<'ts>,
     includes: IncludeBlock<'ts>,
     leftovers_comments: CommentBlock<'ts>,
-    defines: Vec<Define<'ts>>,
+    defines: DefineBlock<'ts>,
+    types: TypeDefinitionBlock<'ts>,
+}
+
+/// The sections of a file, in the order they are printed by the canonical
+/// layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Include,
+    Define,
+    Type,
+    Global,
+    Prototype,
+    Function,
+}
+
+type Item<'a, 'ts> = (Section, usize, ItemKind<'a, 'ts>);
+
+#[derive(Debug, Clone, Copy)]
+enum ItemKind<'a, 'ts> {
+    Include(&'a Include<'ts>),
+    Define(&'a Define<'ts>),
+    Type(&'a TypeDefinition<'ts>),
+    Declaration(Section, &'a Declaration<'ts>),
+    Function(&'a FunctionDefinition<'ts>),
+    Conditional(&'a TopLevelBlock<'ts>),
+}
+
+impl<'a, 'ts> ItemKind<'a, 'ts> {
+    /// Whether two consecutive items are printed as a single block.
+    fn groups_with(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Include(_), Self::Include(_))
+            | (Self::Define(_), Self::Define(_))
+            | (Self::Type(_), Self::Type(_))
+            | (Self::Function(_), Self::Function(_)) => true,
+            (Self::Declaration(a, _), Self::Declaration(b, _)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-struct Define<'ts>(Node<'ts>);
+struct DefineBlock<'ts>(Vec<Define<'ts>>);
+#[derive(Debug, Clone)]
+struct Define<'ts>(CommentBlock<'ts>, Node<'ts>);
 
 #[derive(Debug, Clone)]
 struct CommentBlock<'ts>(Vec<Node<'ts>>);
@@ -151,12 +425,19 @@ struct DeclarationBlock<'ts>(Vec<Declaration<'ts>>);
 #[derive(Debug, Clone)]
 struct Declaration<'ts>(CommentBlock<'ts>, Node<'ts>);
 
+#[derive(Debug, Clone)]
+struct TypeDefinitionBlock<'ts>(Vec<TypeDefinition<'ts>>);
+#[derive(Debug, Clone)]
+struct TypeDefinition<'ts>(CommentBlock<'ts>, Node<'ts>);
+
 #[derive(Debug, Clone)]
 struct FnDefinitionBlock<'ts>(Vec<FunctionDefinition<'ts>>);
 #[derive(Debug, Clone)]
 struct FunctionDefinition<'ts>(CommentBlock<'ts>, Node<'ts>);
 #[derive(Debug, Clone)]
-struct IncludeBlock<'ts>(CommentBlock<'ts>, Vec<Node<'ts>>);
+struct IncludeBlock<'ts>(Vec<Include<'ts>>);
+#[derive(Debug, Clone)]
+struct Include<'ts>(CommentBlock<'ts>, Node<'ts>);
 
 impl<'ts> Default for CommentBlock<'ts> {
     fn default() -> Self {
@@ -170,140 +451,638 @@ fn get_data(_: &()) -> &'_ [u8] {
 
 impl<'ts> FnDefinitionBlock<'ts> {
     pub fn format(&self, ident_value: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
-        for def in &self.0 {
-            //dbg!(def.1.utf8_text(get_data(&())).unwrap());
+        for (idx, def) in self.0.iter().enumerate() {
+            if idx != 0 {
+                writeln!(fmt)?;
+            }
+            def.format(ident_value, fmt)?;
         }
         Ok(())
     }
 }
 
 fn tabbed_len(s: &str) -> usize {
+    let tab = tab_width();
     let mut len = 0;
     for chr in s.chars() {
-        len += 1;
         if chr == '\t' {
-            len += 4 - len % 4;
+            len += tab - len % tab;
+        } else {
+            len += 1;
         }
     }
     len
 }
 
-impl<'ts> DeclarationBlock<'ts> {
-    pub fn format(&self, ident: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
-        if !self.0.is_empty() {
-            let mut cursor = (self.0)[0].1.walk();
-            let mut func_defs = self
-                .0
-                .iter()
-                .map(|def| {
-                    let mut childs = def.1.children(&mut cursor);
-                    let mut ty = childs
-                        .next()
-                        .unwrap()
-                        .utf8_text(get_data(&()))
-                        .unwrap()
-                        .trim()
-                        .to_string();
-                    let func = childs.next().unwrap();
-                    ty.push('\t');
-                    (ty, func)
-                })
-                .collect::<Vec<_>>();
-            let mut aligned = false;
-            let mut current_max = 0;
-            while !aligned {
-                aligned = true;
-                for (ty, _) in &mut func_defs {
-                    let mut cur = tabbed_len(ty);
-                    match current_max.cmp(&cur) {
-                        std::cmp::Ordering::Greater => {
-                            aligned = false;
-                            while cur < current_max {
-                                ty.push('\t');
-                                cur = tabbed_len(ty);
-                            }
-                        }
-                        std::cmp::Ordering::Less => {
-                            current_max = cur;
-                            aligned = false;
-                        }
-                        std::cmp::Ordering::Equal => {
-                            aligned &= true;
-                        }
+/// Pads every string with tabs until they all end on the same column.
+fn align_tabs<'a>(cells: impl IntoIterator<Item = &'a mut String>) {
+    align_tabs_from(cells, 0)
+}
+
+/// Same as `align_tabs`, the strings ending at least on column `min`.
+fn align_tabs_from<'a>(cells: impl IntoIterator<Item = &'a mut String>, min: usize) {
+    let mut cells = cells.into_iter().collect::<Vec<_>>();
+    let mut aligned = false;
+    let mut current_max = min;
+    while !aligned {
+        aligned = true;
+        for ty in &mut cells {
+            let mut cur = tabbed_len(ty);
+            match current_max.cmp(&cur) {
+                std::cmp::Ordering::Greater => {
+                    aligned = false;
+                    while cur < current_max {
+                        ty.push('\t');
+                        cur = tabbed_len(ty);
                     }
                 }
+                std::cmp::Ordering::Less => {
+                    current_max = cur;
+                    aligned = false;
+                }
+                std::cmp::Ordering::Equal => {
+                    aligned &= true;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `node` is a single line directive, which ends with its newline.
+fn is_directive_line(node: Node<'_>) -> bool {
+    matches!(
+        node.kind(),
+        "preproc_include" | "preproc_def" | "preproc_function_def" | "preproc_call"
+    )
+}
+
+/// The comment written after `node` on the same line, printed at the end of
+/// the line `node` is printed on.
+///
+/// The comment after the `#endif` of a conditional block is the one of the
+/// block, which is printed again from its condition.
+fn trailing_comment(node: Node<'_>) -> Option<Node<'_>> {
+    if is_directive_line(node) {
+        // the newline is part of the directive, its comment is a child
+        let mut cursor = node.walk();
+        let comment = node
+            .named_children(&mut cursor)
+            .find(|n| n.kind() == "comment");
+        return comment.filter(|c| c.start_position().row == node.start_position().row);
+    }
+    node.next_named_sibling()
+        .filter(|n| n.kind() == "comment" && n.start_position().row == node.end_position().row)
+}
+
+/// Whether a comment ends a line of code, the printers that support it
+/// printing it with that code instead of with the comments above the next
+/// node.
+fn is_trailing(comment: Node<'_>) -> bool {
+    let Some(prev) = comment.prev_named_sibling() else {
+        return false;
+    };
+    let printed = comment.parent().is_some_and(|parent| match parent.kind() {
+        "case_statement" => parent.child_by_field_name("value") != Some(prev),
+        "translation_unit"
+        | "compound_statement"
+        | "field_declaration_list"
+        | "enumerator_list" => true,
+        kind => kind.starts_with("preproc_"),
+    });
+    printed && prev.kind() != "comment" && trailing_comment(prev) == Some(comment)
+}
+
+/// Splits the value of a `#define` before the `//` comment that ends it, which
+/// tree-sitter keeps in the value.
+fn split_line_comment(value: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, chr) in value.char_indices() {
+        match (quote, chr) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), chr) if chr == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(chr),
+            (None, '/') if value[idx..].starts_with("//") => {
+                return (value[..idx].trim_end(), Some(&value[idx..]));
+            }
+            (None, _) => (),
+        }
+    }
+    (value, None)
+}
+
+/// The column a trailing comment printed at `column` ends on.
+fn commented_len(column: usize, comment: &str) -> usize {
+    tabbed_len(&format!("{}{}", " ".repeat(column), comment.trim()))
+}
+
+/// Prints lines of code, each followed by its trailing comment if it has one.
+/// The comments of consecutive lines are aligned with tabs, the ones that
+/// would go past the line width being printed above their code instead.
+///
+/// The code can span several lines, the comment then ends the last one.
+fn write_commented(
+    lines: &[(String, Option<&str>)],
+    fmt: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    let tab = tab_width();
+    let next_tab = |len: usize| len + tab - len % tab;
+    let last_line = |code: &str| code.rsplit('\n').next().unwrap_or_default().to_string();
+    let last_len = |code: &str| tabbed_len(&last_line(code));
+
+    // comments that fit on their line, the ones moved above their code
+    // splitting the lines into independently aligned runs
+    let mut inline = lines
+        .iter()
+        .map(|(code, comment)| {
+            comment.is_some_and(|c| commented_len(next_tab(last_len(code)), c) <= max_columns())
+        })
+        .collect::<Vec<_>>();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let (code, comment) = &lines[idx];
+        if !inline[idx] {
+            if let Some(comment) = comment {
+                let ident = code.len() - code.trim_start_matches('\t').len();
+                format_comments(&[comment], ident, fmt)?;
             }
-            for (ty, def) in func_defs {
+            writeln!(fmt, "{code}")?;
+            idx += 1;
+            continue;
+        }
+        let run = &lines[idx..idx + inline[idx..].iter().take_while(|&&i| i).count()];
+        let column = run
+            .iter()
+            .map(|(code, _)| next_tab(last_len(code)))
+            .max()
+            .unwrap_or(0);
+        let overflowing = run
+            .iter()
+            .any(|(_, c)| commented_len(column, c.unwrap()) > max_columns());
+        if overflowing {
+            // the longest line is the one pushing the others too far
+            let longest = (0..run.len()).max_by_key(|&i| last_len(&run[i].0)).unwrap();
+            inline[idx + longest] = false;
+            continue;
+        }
+        for (code, comment) in run {
+            let mut last = last_line(code);
+            let head = &code[..code.len() - last.len()];
+            while tabbed_len(&last) < column {
+                last.push('\t');
+            }
+            writeln!(fmt, "{head}{last}{}", comment.unwrap().trim())?;
+        }
+        idx += run.len();
+    }
+    Ok(())
+}
+
+impl<'ts> DefineBlock<'ts> {
+    pub fn format(&self, ident: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
+        let data = get_data(&());
+        let mut defines = self
+            .0
+            .iter()
+            .map(|def| {
+                let mut name = String::from("#");
                 for _ in 0..ident {
-                    write!(fmt, "\t")?;
+                    name.push(' ');
+                }
+                if def.1.kind() == "preproc_call" {
+                    let field = |name| def.1.child_by_field_name(name).map(syntax::text);
+                    let directive = field("directive").unwrap_or_default();
+                    name.push_str(directive.trim_start_matches(['#', ' ', '\t']));
+                    let (argument, comment) =
+                        split_line_comment(field("argument").unwrap_or_default().trim());
+                    if !argument.is_empty() {
+                        name.push(' ');
+                        name.push_str(argument);
+                    }
+                    let comment = comment.or_else(|| trailing_comment(def.1).map(syntax::text));
+                    return (name, "", (&def.0, None), comment);
+                }
+                name.push_str("define ");
+                if let Some(n) = def.1.child_by_field_name("name") {
+                    name.push_str(n.utf8_text(data).unwrap());
+                }
+                if let Some(params) = def.1.child_by_field_name("parameters") {
+                    let mut cursor = params.walk();
+                    let params = params
+                        .children(&mut cursor)
+                        .filter(|p| !matches!(p.kind(), "(" | "," | ")"))
+                        .map(|p| p.utf8_text(data).unwrap())
+                        .collect::<Vec<_>>();
+                    name.push_str(&format!("({})", params.join(", ")));
                 }
-                writeln!(fmt, "{ty}{};", def.utf8_text(get_data(&())).unwrap())?;
+                let value = def
+                    .1
+                    .child_by_field_name("value")
+                    .map(|v| v.utf8_text(data).unwrap().trim())
+                    .unwrap_or_default();
+                // a last `\` only continues the macro on an empty line
+                let value = value.strip_suffix('\\').map_or(value, str::trim_end);
+                let (value, comment) = split_line_comment(value);
+                // the comment of the first line of a macro spanning several
+                // ones goes above it, never after a `\`
+                let first_line = trailing_comment(def.1).map(syntax::text);
+                let (comment, above) = match comment {
+                    Some(comment) => (Some(comment), None),
+                    None if value.contains('\n') => (None, first_line),
+                    None => (first_line, None),
+                };
+                if !value.is_empty() {
+                    name.push('\t');
+                }
+                (name, value, (&def.0, above), comment)
+            })
+            .collect::<Vec<_>>();
+        align_tabs(
+            defines
+                .iter_mut()
+                .filter(|(_, value, _, _)| !value.is_empty())
+                .map(|(name, _, _, _)| name),
+        );
+        let mut lines = Vec::new();
+        for (name, value, (comments, above), comment) in defines {
+            if !comments.0.is_empty() || above.is_some() {
+                write_commented(&std::mem::take(&mut lines), fmt)?;
+                comments.format(0, fmt)?;
             }
+            if let Some(above) = above {
+                format_comments(&[above], 0, fmt)?;
+            }
+            lines.push((format!("{name}{value}"), comment));
         }
-        Ok(())
+        write_commented(&lines, fmt)
+    }
+}
+
+impl<'ts> TypeDefinitionBlock<'ts> {
+    pub fn format(&self, ident: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut single_lines = self
+            .0
+            .iter()
+            .filter(|def| !def.has_body())
+            .map(|def| def.single_line())
+            .collect::<Vec<_>>();
+        align_tabs(
+            single_lines
+                .iter_mut()
+                .filter(|(_, rest)| !rest.is_empty())
+                .map(|(ty, _)| ty),
+        );
+        let mut single_lines = single_lines.into_iter();
+        let mut lines = Vec::new();
+        for (idx, def) in self.0.iter().enumerate() {
+            if def.has_body() {
+                write_commented(&std::mem::take(&mut lines), fmt)?;
+                if idx != 0 {
+                    writeln!(fmt)?;
+                }
+                def.format(ident, fmt)?;
+                if idx + 1 != self.0.len() && !self.0[idx + 1].has_body() {
+                    writeln!(fmt)?;
+                }
+                continue;
+            }
+            let (ty, rest) = single_lines.next().unwrap();
+            if !def.0 .0.is_empty() {
+                write_commented(&std::mem::take(&mut lines), fmt)?;
+                def.0.format(ident, fmt)?;
+            }
+            let comment = trailing_comment(def.1).map(syntax::text);
+            lines.push((format!("{}{ty}{rest}", "\t".repeat(ident)), comment));
+        }
+        write_commented(&lines, fmt)
+    }
+}
+
+impl<'ts> DeclarationBlock<'ts> {
+    pub fn format(&self, ident: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut func_defs = self
+            .0
+            .iter()
+            .map(|def| {
+                let (ty, func) = def.columns();
+                (format!("{}{ty}", "\t".repeat(ident)), func, &def.0)
+            })
+            .collect::<Vec<_>>();
+        align_tabs_from(
+            func_defs.iter_mut().map(|(ty, _, _)| ty),
+            config::with(|c| c.signature_column),
+        );
+        let mut lines = Vec::new();
+        for ((ty, def, comments), decl) in func_defs.into_iter().zip(&self.0) {
+            if !comments.0.is_empty() {
+                write_commented(&std::mem::take(&mut lines), fmt)?;
+                comments.format(ident, fmt)?;
+            }
+            let comment = trailing_comment(decl.1).map(syntax::text);
+            lines.push((format!("{ty}{def}"), comment));
+        }
+        write_commented(&lines, fmt)
+    }
+}
+
+impl<'ts> IncludeBlock<'ts> {
+    pub fn format(&self, ident: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut includes = self
+            .0
+            .iter()
+            .filter_map(|i| i.1.child_by_field_name("path").map(|path| (path, i)))
+            .map(|(path, include)| {
+                let group = match path.kind() {
+                    "system_lib_string" => 0,
+                    "string_literal" => 1,
+                    _ => 2,
+                };
+                (group, path.utf8_text(get_data(&())).unwrap(), include)
+            })
+            .collect::<Vec<_>>();
+        match config::with(|c| c.include_order) {
+            config::IncludeOrder::Source => (),
+            config::IncludeOrder::Grouped => includes.sort_by_key(|&(group, _, _)| group),
+            config::IncludeOrder::Sorted => includes.sort_by_key(|&(group, path, _)| (group, path)),
+        }
+        // the comments written above an include move along with it
+        let mut lines = Vec::new();
+        for (_, path, include) in includes {
+            if !include.0 .0.is_empty() {
+                write_commented(&std::mem::take(&mut lines), fmt)?;
+                include.0.format(0, fmt)?;
+            }
+            let comment = trailing_comment(include.1).map(syntax::text);
+            lines.push((format!("#{}include {path}", " ".repeat(ident)), comment));
+        }
+        write_commented(&lines, fmt)
     }
 }
 
 impl<'ts> CommentBlock<'ts> {
     pub fn format(&self, ident_value: usize, fmt: &mut impl std::io::Write) -> std::io::Result<()> {
-        let mut data = get_data(&());
-        let nodes = self.0.as_slice();
-        let mut special_comment = false;
-        let multi_line = nodes
+        let texts = self
+            .0
             .iter()
-            .map(|&n| data.text(n).next().unwrap())
-            .map(|b| unsafe { std::str::from_utf8_unchecked(b) })
-            .inspect(|s| special_comment |= s.starts_with("/*R"))
-            .any(|s| s.starts_with("/*") || s.ends_with("*/"));
-
-        let options = if !multi_line {
-            textwrap::Options::new(80 - 4 * ident_value)
-                .initial_indent("//\t")
-                .subsequent_indent("//\t")
-        } else {
-            textwrap::Options::new(80 - 4 * ident_value)
-        };
-        let comment_text = nodes
-            .iter()
-            .map(|&n| data.text(n).next().unwrap())
-            .map(|b| unsafe { std::str::from_utf8_unchecked(b) })
-            .map(str::trim)
-            .map(|s: &str| s.trim_start_matches(if special_comment { "/*R" } else { "/*" }))
-            .map(|s: &str| s.trim_end_matches(if special_comment { "R*/" } else { "*/" }))
-            .map(|s: &str| s.trim_start_matches("//"))
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .fold(String::new(), |mut output, s| {
-                output.push('\n');
-                output.push_str(s);
-                output
-            });
-        let cmt_text = &comment_text[(1.min(comment_text.len()))..];
+            .map(|&n| comment_source(n))
+            .collect::<Vec<_>>();
+        format_comments(&texts, ident_value, fmt)
+    }
+}
 
-        let (comment_start, comment_end) = match (multi_line, special_comment) {
-            (true, true) => ("/*R\n", "R*/\n"),
-            (true, false) => ("/*\n", "*/\n"),
-            (false, _) => ("", ""),
-        };
-        if !cmt_text.is_empty() {
-            let wraped = textwrap::wrap(cmt_text, options);
+/// The text of a comment, preceded by its indentation when nothing else is
+/// written before it on its first line.
+fn comment_source(node: Node<'_>) -> &'static str {
+    let data = get_data(&());
+    let line_start = node.start_byte() - node.start_position().column;
+    let text = &data[line_start..node.end_byte()];
+    let text = match text[..node.start_position().column]
+        .iter()
+        .all(u8::is_ascii_whitespace)
+    {
+        true => text,
+        false => &data[node.start_byte()..node.end_byte()],
+    };
+    unsafe { std::str::from_utf8_unchecked(text) }
+}
+
+/// Prints the source text of consecutive comments as a single block, all
+/// `//` lines or a single `/* */` if any of them is one.
+///
+/// `/*R ... R*/` comments are kept as written and printed on their own,
+/// splitting the block around them.
+fn format_comments(
+    texts: &[&str],
+    ident_value: usize,
+    fmt: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    let mut start = 0;
+    for (idx, text) in texts.iter().enumerate() {
+        if text.trim_start().starts_with("/*R") {
+            format_wrapped(&texts[start..idx], ident_value, fmt)?;
+            format_raw(text, ident_value, fmt)?;
+            start = idx + 1;
+        }
+    }
+    format_wrapped(&texts[start..], ident_value, fmt)
+}
+
+/// Prints a raw comment byte for byte, only replacing the indentation of its
+/// first line by `ident_value` tabs on every line.
+fn format_raw(
+    text: &str,
+    ident_value: usize,
+    fmt: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    let indent = &text[..text.len() - text.trim_start().len()];
+    for line in text.trim_start().lines() {
+        let line = line.strip_prefix(indent).unwrap_or(line);
+        if !line.is_empty() {
             for _ in 0..ident_value {
                 write!(fmt, "\t")?;
             }
-            write!(fmt, "{comment_start}")?;
-            for line in wraped {
-                for _ in 0..ident_value {
-                    write!(fmt, "\t")?;
+        }
+        writeln!(fmt, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Whether a line of a comment is laid out by hand, in columns separated by
+/// several spaces or by tabs.
+fn is_aligned(line: &str) -> bool {
+    let content = line.trim();
+    content.contains('\t') || content.contains("  ")
+}
+
+/// The indentation and marker of a list item (`- `, `* `, `1. `...), that its
+/// continuation lines are aligned after.
+fn list_marker(line: &str) -> Option<&str> {
+    let content = line.trim_start();
+    let numbered = content.trim_start_matches(|c: char| c.is_ascii_digit());
+    let rest = match numbered.len() != content.len() {
+        true => numbered.strip_prefix(". ").or(numbered.strip_prefix(") ")),
+        false => ["- ", "* ", "+ "]
+            .into_iter()
+            .find_map(|marker| content.strip_prefix(marker)),
+    }?;
+    Some(&line[..line.len() - rest.len()])
+}
+
+/// Wraps a line of comment printed after `lead` (indentation and prefix) so
+/// that it doesn't go past the line width, continuation lines starting with
+/// `hanging`. Only fails when `lead` itself leaves no room.
+fn wrap_to_fit(line: &str, lead: &str, hanging: &str) -> Vec<String> {
+    // `lead` isn't given to textwrap, it counts tabs as zero columns wide
+    let mut width = max_columns().saturating_sub(tabbed_len(lead)).max(1);
+    loop {
+        let options = textwrap::Options::new(width).subsequent_indent(hanging);
+        let wrapped = textwrap::wrap(line, options)
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let fits = wrapped
+            .iter()
+            .all(|l| tabbed_len(&format!("{lead}{l}")) <= max_columns());
+        if fits || width <= hanging.len() + 1 {
+            return wrapped;
+        }
+        width -= 1;
+    }
+}
+
+/// Removes the indentation of the first line of a comment from all of its
+/// lines.
+fn dedent(text: &str) -> String {
+    let indent = &text[..text.len() - text.trim_start_matches([' ', '\t']).len()];
+    text.lines()
+        .map(|line| line.strip_prefix(indent).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Removes the `**` (or `*`) starting the lines of a `/* */` comment.
+fn strip_continuations(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let content = line.trim_start();
+            let rest = content
+                .strip_prefix("**")
+                .or_else(|| content.strip_prefix('*'));
+            match rest {
+                Some(rest) if rest.is_empty() || rest.starts_with([' ', '\t']) => {
+                    &rest[1.min(rest.len())..]
+                }
+                _ => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The mark the lines of the block comments of `texts` start with after
+/// their first one, `**` or `*`, if they all use the same.
+fn continuation_mark(texts: &[&str]) -> Option<&'static str> {
+    let mut marks = texts
+        .iter()
+        .filter(|s| s.starts_with("/*"))
+        .flat_map(|s| s.lines().skip(1))
+        .map(str::trim_start)
+        .filter(|line| !line.is_empty() && *line != "*/")
+        .map(|line| match line {
+            _ if line.starts_with("**") => Some("**"),
+            _ if line.starts_with('*') => Some("*"),
+            _ => None,
+        });
+    let first = marks.next()??;
+    marks.all(|mark| mark == Some(first)).then_some(first)
+}
+
+/// Prints comments that aren't raw, wrapping their lines. Code between
+/// ```` ``` ```` fences and lines aligned in columns are kept as they are,
+/// list items are wrapped after their marker.
+///
+/// The comments are written following the configured `CommentStyle`.
+fn format_wrapped(
+    texts: &[&str],
+    ident_value: usize,
+    fmt: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    let style = config::with(|c| c.comment_style);
+    let multi_line = match style {
+        config::CommentStyle::Source => texts
+            .iter()
+            .any(|s| s.starts_with("/*") || s.ends_with("*/")),
+        config::CommentStyle::Block => true,
+        config::CommentStyle::Line => false,
+    };
+
+    // the source style keeps the marks starting the lines of block comments
+    let mark = match style {
+        config::CommentStyle::Source => continuation_mark(texts),
+        _ => None,
+    };
+    let prefix = match (multi_line, style, mark) {
+        (false, _, _) => "//\t",
+        (true, config::CommentStyle::Block, _) | (true, _, Some("**")) => "** ",
+        (true, _, Some(_)) => " * ",
+        (true, _, None) => "",
+    };
+    let lead = format!("{}{prefix}", "\t".repeat(ident_value));
+    let comment_text = texts
+        .iter()
+        .map(|s| dedent(s))
+        .map(|s| s.trim().to_string())
+        .map(|s| match s.strip_prefix("/*") {
+            Some(block) if style != config::CommentStyle::Source || mark.is_some() => {
+                strip_continuations(block.trim_end_matches("*/"))
+            }
+            _ => s
+                .trim_start_matches("/*")
+                .trim_end_matches("*/")
+                .to_string(),
+        })
+        .map(|s| match s.strip_prefix("//") {
+            // keep the indentation of the text, past the space after `//`
+            Some(line) => line
+                .strip_prefix([' ', '\t'])
+                .unwrap_or(line)
+                .trim_end()
+                .to_string(),
+            None => s.trim().to_string(),
+        })
+        .filter(|s| !s.is_empty())
+        .fold(String::new(), |mut output, s| {
+            output.push('\n');
+            output.push_str(&s);
+            output
+        });
+    let cmt_text = &comment_text[(1.min(comment_text.len()))..];
+
+    let (comment_start, comment_end) = match (multi_line, prefix) {
+        (true, " * ") => ("/*\n", " */\n"),
+        (true, _) => ("/*\n", "*/\n"),
+        (false, _) => ("", ""),
+    };
+    if !cmt_text.is_empty() {
+        let mut wraped = Vec::new();
+        let mut in_fence = false;
+        for line in cmt_text.lines() {
+            let fence = line.trim_start().starts_with("```");
+            let line = line.trim_end();
+            if fence || in_fence || is_aligned(line) {
+                in_fence ^= fence;
+                match tabbed_len(&format!("{lead}{line}")) <= max_columns() {
+                    true => wraped.push(line.to_string()),
+                    false => wraped.extend(wrap_to_fit(line, &lead, "")),
                 }
-                writeln!(fmt, "{}", line)?;
+                continue;
             }
+            let hanging = list_marker(line)
+                .map(|marker| {
+                    " ".repeat(tabbed_len(&format!("{lead}{marker}")) - tabbed_len(&lead))
+                })
+                .unwrap_or_default();
+            wraped.extend(wrap_to_fit(line, &lead, &hanging));
+        }
+        if !comment_start.is_empty() {
+            for _ in 0..ident_value {
+                write!(fmt, "\t")?;
+            }
+            write!(fmt, "{comment_start}")?;
+        }
+        for line in wraped {
+            writeln!(fmt, "{}", format!("{lead}{line}").trim_end())?;
+        }
+        if !comment_end.is_empty() {
             for _ in 0..ident_value {
                 write!(fmt, "\t")?;
             }
             write!(fmt, "{comment_end}")?;
         }
-        Ok(())
     }
+    Ok(())
 }
 
 impl<'ts> TopLevelBlock<'ts> {
@@ -318,64 +1097,185 @@ impl<'ts> TopLevelBlock<'ts> {
                 writeln!(fmt, "{}", node.utf8_text(get_data(&())).unwrap())?;
             }
             TopLevelBlock::PreprocIf(if_data, tplb) => {
+                let directive = match if_data.ifnode.map(|n| n.kind()).unwrap_or("") {
+                    "#ifndef" => "ifndef",
+                    "#ifdef" => "ifdef",
+                    "#if" => "if",
+                    "#elif" => "elif",
+                    "#elifdef" => "elifdef",
+                    "#elifndef" => "elifndef",
+                    "#else" => "else",
+                    _ => "",
+                };
+                if_data.comments.format(0, fmt)?;
                 write!(fmt, "#",)?;
                 for _ in 0..ident {
                     write!(fmt, " ")?;
                 }
-                writeln!(
-                    fmt,
-                    "{} {}",
-                    match if_data.ifnode.map(|n| n.kind()).unwrap_or("") {
-                        "#ifndef" => "ifndef",
-                        "#ifdef" => "ifdef",
-                        "#if" => "if",
-                        _ => "",
-                    },
-                    if_data
-                        .ifnode_identifier
-                        .and_then(|n| n.utf8_text(get_data(&())).ok())
-                        .unwrap_or(""),
-                )?;
-                for tlb in &if_data.tlb {
-                    tlb.format(filename, ident + 1, fmt)?;
+                match if_data.condition() {
+                    Some(cond) => writeln!(fmt, "{directive} {cond}")?,
+                    None => writeln!(fmt, "{directive}")?,
                 }
+                tplb.format(filename, ident + 1, &if_data.tlb, fmt)?;
 
-                tplb.format(filename, ident + 1, fmt)?;
+                if let Some(alternative) = &if_data.alternative {
+                    alternative.format(filename, ident, fmt)?;
+                }
+                // the `#endif` belongs to the first branch of the chain
+                if matches!(directive, "elif" | "elifdef" | "elifndef" | "else") {
+                    return Ok(());
+                }
 
-                write!(fmt, "#",)?;
-                for _ in 0..ident {
-                    write!(fmt, " ")?;
+                // a condition too long to be repeated within the limit isn't
+                let endif = format!("#{}endif", " ".repeat(ident));
+                match if_data.condition().map(|cond| format!("{endif} // {cond}")) {
+                    Some(line) if tabbed_len(&line) <= max_columns() => writeln!(fmt, "{line}")?,
+                    _ => writeln!(fmt, "{endif}")?,
                 }
-                writeln!(
-                    fmt,
-                    "endif // {}",
-                    if_data
-                        .ifnode_identifier
-                        .and_then(|n| n.utf8_text(get_data(&())).ok())
-                        .unwrap_or(""),
-                )?;
-                writeln!(fmt)?;
             }
             TopLevelBlock::Plain(tp) => {
-                tp.format(filename, ident, fmt)?;
+                tp.format(filename, ident, &[], fmt)?;
             }
         }
         Ok(())
     }
 }
 
+impl<'ts> TopLevelBlock<'ts> {
+    /// Where a conditional block goes when reordering: in the first section
+    /// it contains something for, at the position of its `#if`.
+    fn position(&self) -> Option<(Section, usize)> {
+        match self {
+            TopLevelBlock::PreprocIf(if_data, def) => {
+                let section = def
+                    .first_section()
+                    .into_iter()
+                    .chain(
+                        if_data
+                            .tlb
+                            .iter()
+                            .filter_map(|b| b.position())
+                            .map(|(s, _)| s),
+                    )
+                    .chain(
+                        if_data
+                            .alternative
+                            .iter()
+                            .filter_map(|b| b.position())
+                            .map(|(s, _)| s),
+                    )
+                    .min()
+                    .unwrap_or(Section::Include);
+                Some((section, if_data.node.start_byte()))
+            }
+            TopLevelBlock::Error(node) => Some((Section::Function, node.start_byte())),
+            TopLevelBlock::Plain(_) => None,
+        }
+    }
+}
+
+impl<'ts> Define<'ts> {
+    /// Whether this is a `#pragma once`, which stays first whatever the
+    /// layout.
+    fn is_pragma_once(&self) -> bool {
+        let field = |name| self.1.child_by_field_name(name).map(syntax::text);
+        field("directive") == Some("#pragma") && field("argument").map(str::trim) == Some("once")
+    }
+
+    /// Whether this is the `# define NAME` right after an `#ifndef NAME`,
+    /// which stays first whatever the layout.
+    fn is_include_guard(&self) -> bool {
+        let parent = self.1.parent().filter(|p| p.kind() == "preproc_ifdef");
+        let guard = parent
+            .filter(|p| p.child(0).is_some_and(|n| n.kind() == "#ifndef"))
+            .and_then(|p| p.child_by_field_name("name"));
+        let name = self.1.child_by_field_name("name");
+        match (guard, name) {
+            (Some(guard), Some(name)) => {
+                self.1.prev_named_sibling() == Some(guard)
+                    && guard.utf8_text(get_data(&())).ok() == name.utf8_text(get_data(&())).ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'ts> Declaration<'ts> {
+    /// The specifiers of the declaration followed by a tab, and its
+    /// declarators with their `*` glued to the names.
+    fn columns(&self) -> (String, String) {
+        let (ty, decls) = function::declaration(self.1);
+        (format!("{ty}\t"), decls)
+    }
+
+    /// Whether the declaration declares a function rather than a variable.
+    fn is_prototype(&self) -> bool {
+        let mut decl = self.1.child_by_field_name("declarator");
+        while let Some(node) = decl {
+            match node.kind() {
+                "function_declarator" => {
+                    return node
+                        .child_by_field_name("declarator")
+                        .is_some_and(|n| n.kind() != "parenthesized_declarator");
+                }
+                "pointer_declarator" | "attributed_declarator" => {
+                    decl = node
+                        .child_by_field_name("declarator")
+                        .or_else(|| node.named_child(0));
+                }
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Whether the initializers of the declaration use one of the names
+    /// declared by `other`.
+    fn uses(&self, other: &Self) -> bool {
+        let mut cursor = other.1.walk();
+        let names = other
+            .1
+            .children_by_field_name("declarator", &mut cursor)
+            .filter_map(syntax::declarator_name)
+            .map(syntax::text)
+            .collect::<Vec<_>>();
+        let mut cursor = self.1.walk();
+        let mut stack = self
+            .1
+            .children_by_field_name("declarator", &mut cursor)
+            .filter_map(|d| d.child_by_field_name("value"))
+            .collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            if node.kind() == "identifier" && names.contains(&syntax::text(node)) {
+                return true;
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.named_children(&mut cursor));
+        }
+        false
+    }
+}
+
 impl<'ts> ToplevelDefinition<'ts> {
-    fn from_tree_inner(root: &Node<'ts>, append_to: &mut Vec<TopLevelBlock<'ts>>, first: bool) {
+    fn from_tree_inner(
+        root: &Node<'ts>,
+        comments: CommentBlock<'ts>,
+        append_to: &mut Vec<TopLevelBlock<'ts>>,
+        first: bool,
+    ) {
         let mut walker = root.walk();
         let mut children = root.children(&mut walker).collect::<VecDeque<_>>();
         let mut out = ToplevelDefinition {
             header: CommentBlock(Vec::with_capacity(11)),
             declarations: DeclarationBlock(Vec::with_capacity(8)),
             functions: FnDefinitionBlock(Vec::with_capacity(5)),
-            includes: IncludeBlock(CommentBlock(Vec::new()), Vec::with_capacity(4)),
+            includes: IncludeBlock(Vec::with_capacity(4)),
             leftovers_comments: CommentBlock(Vec::new()),
-            defines: Vec::new(), // Defines<'ts>>,
+            defines: DefineBlock(Vec::new()),
+            types: TypeDefinitionBlock(Vec::new()),
         };
+        // the header may have been written with another line width
+        let header_width = children.front().map(|n| n.end_position().column);
         if first
             && children
                 .iter()
@@ -383,7 +1283,7 @@ impl<'ts> ToplevelDefinition<'ts> {
                 .enumerate()
                 .filter(|&(row, n)| {
                     n.kind() == "comment"
-                        && n.end_position().column == 80
+                        && Some(n.end_position().column) == header_width
                         && n.start_position().column == 0
                         && n.start_position().row == row
                         && n.end_position().row == row
@@ -407,46 +1307,69 @@ impl<'ts> ToplevelDefinition<'ts> {
         let mut ifdata: Option<Node<'ts>> = None;
         let mut ifdata_identifier: Option<Node<'ts>> = None;
         let mut inner_stuff = Vec::new();
+        let mut alternative = Vec::with_capacity(1);
 
         while let Some(node) = children.pop_front() {
             match node.kind() {
-                "\n" => (),
+                "\n" | ";" => (),
                 "ERROR" => {
                     append_to.push(TopLevelBlock::Error(node));
                     return;
                 }
+                "comment" if is_trailing(node) => (),
                 "comment" => latest_comment_block.0.push(node),
                 "function_definition" => out.functions.0.push(FunctionDefinition(
                     std::mem::take(&mut latest_comment_block),
                     node,
                 )),
+                // `struct s_pt { ... } g_origin;` is laid out like a type
+                "declaration"
+                    if node
+                        .child_by_field_name("type")
+                        .is_some_and(|t| t.child_by_field_name("body").is_some()) =>
+                {
+                    out.types.0.push(TypeDefinition(
+                        std::mem::take(&mut latest_comment_block),
+                        node,
+                    ))
+                }
                 "declaration" => out
                     .declarations
                     .0
                     .push(Declaration(std::mem::take(&mut latest_comment_block), node)),
+                "type_definition" | "struct_specifier" | "enum_specifier" | "union_specifier" => {
+                    out.types.0.push(TypeDefinition(
+                        std::mem::take(&mut latest_comment_block),
+                        node,
+                    ))
+                }
                 "preproc_ifdef" | "preproc_if" => Self::from_tree_inner(
                     &node,
+                    std::mem::take(&mut latest_comment_block),
                     if first { append_to } else { &mut inner_stuff },
                     false,
                 ),
-                "preproc_include" => {
-                    out.includes.1.push(node);
-                    (out.includes.0).0.append(&mut latest_comment_block.0);
-                }
-                "preproc_def" => {
-                    out.defines.push(Define(node));
+                "preproc_elif" | "preproc_elifdef" | "preproc_else" if !first => {
+                    Self::from_tree_inner(&node, CommentBlock::default(), &mut alternative, false)
                 }
+                "preproc_include" => out
+                    .includes
+                    .0
+                    .push(Include(std::mem::take(&mut latest_comment_block), node)),
+                // `#undef`, `#pragma`, `#error`... printed as written
+                "preproc_def" | "preproc_function_def" | "preproc_call" => out
+                    .defines
+                    .0
+                    .push(Define(std::mem::take(&mut latest_comment_block), node)),
 
-                "#if" if !first => {
+                "#if" | "#elif" if !first => {
                     ifdata = Some(node);
-                    ifdata_identifier = children
-                        .front()
-                        .map(|n| n.kind() == "binary_expression")
-                        .unwrap_or_default()
-                        .then(|| children.pop_front())
-                        .flatten();
+                    ifdata_identifier = root.child_by_field_name("condition");
+                    if children.front() == ifdata_identifier.as_ref() {
+                        children.pop_front();
+                    }
                 }
-                "#ifndef" | "#ifdef" if !first => {
+                "#ifndef" | "#ifdef" | "#elifdef" | "#elifndef" if !first => {
                     ifdata = Some(node);
                     ifdata_identifier = children
                         .front()
@@ -455,11 +1378,13 @@ impl<'ts> ToplevelDefinition<'ts> {
                         .then(|| children.pop_front())
                         .flatten();
                 }
+                "#else" if !first => ifdata = Some(node),
                 "#endif" if !first => (),
-                unknown => eprintln!(
-                    "Unknown Node type `{unknown}`\n\tText: '{:?}'",
-                    node.utf8_text(get_data(&())).unwrap()
-                ),
+                // reported by `unsupported`, never printed
+                _ => match first {
+                    true => append_to.push(TopLevelBlock::Error(node)),
+                    false => inner_stuff.push(TopLevelBlock::Error(node)),
+                },
             }
         }
 
@@ -468,7 +1393,14 @@ impl<'ts> ToplevelDefinition<'ts> {
             TopLevelBlock::Plain(out)
         } else {
             TopLevelBlock::PreprocIf(
-                PreprocIfData::from_node(*root, ifdata, ifdata_identifier, inner_stuff),
+                PreprocIfData::from_node(
+                    *root,
+                    comments,
+                    ifdata,
+                    ifdata_identifier,
+                    inner_stuff,
+                    alternative.pop().map(Box::new),
+                ),
                 out,
             )
         });
@@ -476,36 +1408,265 @@ impl<'ts> ToplevelDefinition<'ts> {
     pub fn from_tree(root: &'ts Tree) -> Vec<TopLevelBlock<'ts>> {
         let root_node = root.root_node();
         let mut out_vec = Vec::with_capacity(2);
-        Self::from_tree_inner(&root_node, &mut out_vec, true);
+        Self::from_tree_inner(&root_node, CommentBlock::default(), &mut out_vec, true);
         out_vec
     }
 
+    /// Every printable element of the definition and of the `nested`
+    /// conditional blocks, ordered following the configured `Layout`.
+    fn items<'a>(&'a self, nested: &'a [TopLevelBlock<'ts>]) -> Vec<Item<'a, 'ts>> {
+        let mut items = Vec::new();
+        let start = |n: &Node| n.start_byte();
+        items.extend(self.includes.0.iter().map(|i| {
+            let kind = ItemKind::Include(i);
+            (Section::Include, start(&i.1), kind)
+        }));
+        items.extend(self.defines.0.iter().map(|d| {
+            let kind = ItemKind::Define(d);
+            match d.is_include_guard() || d.is_pragma_once() {
+                true => (Section::Include, 0, kind),
+                false => (Section::Define, start(&d.1), kind),
+            }
+        }));
+        items.extend(self.types.0.iter().map(|t| {
+            let kind = ItemKind::Type(t);
+            (Section::Type, start(&t.1), kind)
+        }));
+        items.extend(self.declarations.0.iter().map(|d| {
+            let section = match d.is_prototype() {
+                true => Section::Prototype,
+                false => Section::Global,
+            };
+            (section, start(&d.1), ItemKind::Declaration(section, d))
+        }));
+        items.extend(self.functions.0.iter().map(|f| {
+            let kind = ItemKind::Function(f);
+            (Section::Function, start(&f.1), kind)
+        }));
+        items.extend(nested.iter().filter_map(|block| {
+            let (section, start) = block.position()?;
+            Some((section, start, ItemKind::Conditional(block)))
+        }));
+        match config::with(|c| c.layout) {
+            config::Layout::Canonical => {
+                Self::keep_meaning(&mut items);
+                items.sort_by_key(|&(section, start, _)| (section, start))
+            }
+            config::Layout::Source => items.sort_by_key(|&(_, start, _)| start),
+        }
+        items
+    }
+
+    /// Moves the `items` that reordering would break to another section: the
+    /// defines written before an include, which may configure it, stay among
+    /// the includes, the conditional blocks written after a type definition,
+    /// which may use it, go no higher than the types, and the prototypes used
+    /// by the initializer of a later global stay above it.
+    fn keep_meaning(items: &mut [Item<'_, 'ts>]) {
+        let starts = |items: &[Item<'_, 'ts>], wanted| {
+            items
+                .iter()
+                .filter(|&&(section, _, _)| section == wanted)
+                .map(|&(_, start, _)| start)
+                .collect::<Vec<_>>()
+        };
+        let last_include = starts(items, Section::Include).into_iter().max();
+        let first_type = starts(items, Section::Type).into_iter().min();
+        let globals = items
+            .iter()
+            .filter_map(|&(_, start, kind)| match kind {
+                ItemKind::Declaration(Section::Global, d) => Some((start, d)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (section, start, kind) in items {
+            match kind {
+                ItemKind::Define(_) if last_include.is_some_and(|i| *start < i) => {
+                    *section = Section::Include
+                }
+                ItemKind::Conditional(_) if first_type.is_some_and(|t| t < *start) => {
+                    *section = (*section).max(Section::Type)
+                }
+                ItemKind::Declaration(Section::Prototype, proto)
+                    if globals
+                        .iter()
+                        .any(|&(global, d)| *start < global && d.uses(proto)) =>
+                {
+                    *section = Section::Global
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// The column the names of the prototypes, globals and functions of this
+    /// definition end up on, each block on its own.
+    fn signature_column(&self) -> usize {
+        let declarations = self.declarations.0.iter().map(|d| d.columns().0);
+        let functions = self
+            .functions
+            .0
+            .iter()
+            .map(|f| format!("{}\t", function::signature(f.1).0));
+        declarations
+            .chain(functions)
+            .map(|ty| tabbed_len(&ty))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The first section this definition has something to print in.
+    fn first_section(&self) -> Option<Section> {
+        [
+            (Section::Include, self.includes.0.is_empty()),
+            (Section::Define, self.defines.0.is_empty()),
+            (Section::Type, self.types.0.is_empty()),
+        ]
+        .into_iter()
+        .filter(|&(_, empty)| !empty)
+        .map(|(section, _)| section)
+        .chain(self.declarations.0.iter().map(|d| match d.is_prototype() {
+            true => Section::Prototype,
+            false => Section::Global,
+        }))
+        .chain((!self.functions.0.is_empty()).then_some(Section::Function))
+        .min()
+    }
+
+    /// Prints the definition, along with the `nested` conditional blocks
+    /// found at the same level.
+    ///
+    /// Consecutive elements of the same section are printed as one block (and
+    /// aligned together), blocks being separated by exactly one empty line.
     pub fn format(
         &self,
         filename: &str,
         ident: usize,
+        nested: &[TopLevelBlock<'ts>],
         output: &mut impl std::io::Write,
     ) -> std::io::Result<()> {
-        self.declarations.format(ident, output)?;
-        self.functions.format(ident, output)?;
-        self.leftovers_comments.format(ident, output)?;
+        let items = self.items(nested);
+        let mut printed_any = false;
+        let mut idx = 0;
+        while idx < items.len() {
+            let group_len = 1 + items[idx + 1..]
+                .iter()
+                .take_while(|(_, _, kind)| kind.groups_with(&items[idx].2))
+                .count();
+            let group = items[idx..idx + group_len].iter().map(|(_, _, kind)| kind);
+            idx += group_len;
+            if printed_any {
+                writeln!(output)?;
+            }
+            printed_any = true;
+            match items[idx - group_len].2 {
+                ItemKind::Include(_) => IncludeBlock(
+                    group
+                        .filter_map(|kind| match kind {
+                            ItemKind::Include(i) => Some((*i).clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+                .format(ident, output)?,
+                ItemKind::Define(_) => DefineBlock(
+                    group
+                        .filter_map(|kind| match kind {
+                            ItemKind::Define(d) => Some((*d).clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+                .format(ident, output)?,
+                ItemKind::Type(_) => TypeDefinitionBlock(
+                    group
+                        .filter_map(|kind| match kind {
+                            ItemKind::Type(t) => Some((*t).clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+                .format(0, output)?,
+                ItemKind::Declaration(..) => DeclarationBlock(
+                    group
+                        .filter_map(|kind| match kind {
+                            ItemKind::Declaration(_, d) => Some((*d).clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+                .format(0, output)?,
+                ItemKind::Function(_) => FnDefinitionBlock(
+                    group
+                        .filter_map(|kind| match kind {
+                            ItemKind::Function(f) => Some((*f).clone()),
+                            _ => None,
+                        })
+                        .collect(),
+                )
+                .format(0, output)?,
+                ItemKind::Conditional(block) => block.format(filename, ident, output)?,
+            }
+        }
+        if !self.leftovers_comments.0.is_empty() && printed_any {
+            writeln!(output)?;
+        }
+        self.leftovers_comments.format(0, output)?;
 
         Ok(())
     }
 }
 
+/// The column on which every prototype, global and function name of
+/// `blocks` is aligned with `--align-file`, going through the conditional
+/// blocks the way `format` does.
+fn signature_column(blocks: &[TopLevelBlock<'_>]) -> usize {
+    blocks
+        .iter()
+        .map(|block| match block {
+            TopLevelBlock::PreprocIf(if_data, def) => def
+                .signature_column()
+                .max(signature_column(&if_data.tlb))
+                .max(
+                    if_data
+                        .alternative
+                        .as_deref()
+                        .map(|alt| signature_column(std::slice::from_ref(alt)))
+                        .unwrap_or(0),
+                ),
+            TopLevelBlock::Plain(def) => def.signature_column(),
+            TopLevelBlock::Error(_) => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
 impl<'ts> PreprocIfData<'ts> {
     fn from_node(
         node: Node<'ts>,
+        comments: CommentBlock<'ts>,
         ifnode: Option<Node<'ts>>,
         ifnode_identifier: Option<Node<'ts>>,
         tlb: Vec<TopLevelBlock<'ts>>,
+        alternative: Option<Box<TopLevelBlock<'ts>>>,
     ) -> Self {
         Self {
             node,
+            comments,
             ifnode,
             ifnode_identifier,
             tlb,
+            alternative,
         }
     }
+
+    /// The condition of the directive, `#if` expressions having their spacing
+    /// normalized.
+    fn condition(&self) -> Option<String> {
+        let cond = self.ifnode_identifier?;
+        Some(match self.ifnode.map(|n| n.kind()) {
+            Some("#if" | "#elif") => syntax::expression(cond),
+            _ => cond.utf8_text(get_data(&())).unwrap().to_string(),
+        })
+    }
 }
@@ -0,0 +1,403 @@
+//! Helpers turning tree-sitter nodes back into normalized C source text.
+//!
+//! Everything in here returns a `String` for a single node and never writes
+//! newlines: layout (indentation, braces, line breaks) is the job of the
+//! callers.
+
+use tree_sitter::Node;
+
+use crate::get_data;
+
+pub fn text(node: Node<'_>) -> &'static str {
+    node.utf8_text(get_data(&())).unwrap()
+}
+
+fn named_children(node: Node<'_>) -> Vec<Node<'_>> {
+    let mut cursor = node.walk();
+    node.named_children(&mut cursor)
+        .filter(|n| n.kind() != "comment")
+        .collect()
+}
+
+/// Whether a comment is written inside `node`. The helpers of this module
+/// drop them, the nodes holding one have to be printed some other way.
+pub fn has_comment(node: Node<'_>) -> bool {
+    let mut cursor = node.walk();
+    let has_comment = node.kind() == "comment" || node.children(&mut cursor).any(has_comment);
+    has_comment
+}
+
+/// The specifiers of a declaration (or of the head of a function definition)
+/// and the rest of it up to `end` as it is written, `None` if there is no
+/// comment in there to keep.
+///
+/// The text as written starts at the first declarator, or at the first
+/// comment if it comes before.
+pub fn commented_declarators(node: Node<'_>, end: usize) -> Option<(String, String)> {
+    let mut cursor = node.walk();
+    let children = node
+        .children(&mut cursor)
+        .filter(|n| n.start_byte() < end)
+        .collect::<Vec<_>>();
+    if !children.iter().any(|&n| has_comment(n)) {
+        return None;
+    }
+    let declarator = node.child_by_field_name("declarator");
+    let split = children
+        .iter()
+        .position(|&n| n.kind() == "comment" || Some(n) == declarator)
+        .unwrap_or(0);
+    let ty = children[..split]
+        .iter()
+        .map(|&n| collapsed(n))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let rest = &get_data(&())[children[split].start_byte()..end];
+    Some((ty, String::from_utf8_lossy(rest).into_owned()))
+}
+
+/// Source text of `node` with every whitespace run between two tokens
+/// replaced by a single space.
+///
+/// Used as a fallback for the node kinds that aren't handled explicitly, it
+/// never touches the inside of a token (string contents, comments...).
+pub fn collapsed(node: Node<'_>) -> String {
+    fn inner(node: Node<'_>, last_end: &mut Option<usize>, out: &mut String) {
+        if node.child_count() == 0
+            || node.kind() == "string_literal"
+            || node.kind() == "char_literal"
+        {
+            if last_end.is_some_and(|end| end < node.start_byte()) {
+                out.push(' ');
+            }
+            out.push_str(text(node));
+            *last_end = Some(node.end_byte());
+            return;
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            inner(child, last_end, out);
+        }
+    }
+    let mut out = String::new();
+    inner(node, &mut None, &mut out);
+    out
+}
+
+/// Declaration specifiers of a `declaration`, `function_definition`,
+/// `parameter_declaration`... : everything that comes before the first
+/// declarator, joined by a single space.
+pub fn specifiers(node: Node<'_>) -> String {
+    let mut cursor = node.walk();
+    let mut out = String::new();
+    if cursor.goto_first_child() {
+        loop {
+            let child = cursor.node();
+            if cursor.field_name() == Some("declarator") || !child.is_named() {
+                break;
+            }
+            if child.kind() != "comment" {
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str(&collapsed(child));
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+    out
+}
+
+/// Every `declarator` field of a node, in source order.
+pub fn declarators(node: Node<'_>) -> Vec<Node<'_>> {
+    let mut cursor = node.walk();
+    node.children_by_field_name("declarator", &mut cursor)
+        .collect()
+}
+
+/// A declarator, with the `*` glued to the name (`*name`, `**argv`).
+pub fn declarator(node: Node<'_>) -> String {
+    match node.kind() {
+        "identifier" | "field_identifier" | "type_identifier" | "primitive_type" => {
+            text(node).to_string()
+        }
+        "pointer_declarator" | "abstract_pointer_declarator" => {
+            let mut out = String::from("*");
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                if child.kind() == "type_qualifier" {
+                    out.push_str(text(child));
+                    out.push(' ');
+                }
+            }
+            if let Some(inner) = node.child_by_field_name("declarator") {
+                out.push_str(&declarator(inner));
+            }
+            out
+        }
+        "function_declarator" | "abstract_function_declarator" => {
+            let mut out = node
+                .child_by_field_name("declarator")
+                .map(declarator)
+                .unwrap_or_default();
+            if let Some(params) = node.child_by_field_name("parameters") {
+                out.push_str(&parameters(params));
+            }
+            // `__attribute__((...))` written after the parameters
+            for attribute in named_children(node)
+                .into_iter()
+                .filter(|n| n.kind() == "attribute_specifier")
+            {
+                out.push(' ');
+                out.push_str(&collapsed(attribute));
+            }
+            out
+        }
+        "attributed_declarator" => named_children(node)
+            .into_iter()
+            .map(|child| match child.kind() {
+                "attribute_declaration" => collapsed(child),
+                _ => declarator(child),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        "array_declarator" | "abstract_array_declarator" => {
+            let mut out = node
+                .child_by_field_name("declarator")
+                .map(declarator)
+                .unwrap_or_default();
+            out.push('[');
+            if let Some(size) = node.child_by_field_name("size") {
+                out.push_str(&expression(size));
+            }
+            out.push(']');
+            out
+        }
+        "parenthesized_declarator" | "abstract_parenthesized_declarator" => {
+            let inner = named_children(node)
+                .into_iter()
+                .map(declarator)
+                .collect::<String>();
+            format!("({inner})")
+        }
+        "init_declarator" => {
+            let decl = node
+                .child_by_field_name("declarator")
+                .map(declarator)
+                .unwrap_or_default();
+            match node.child_by_field_name("value") {
+                Some(value) => format!("{decl} = {}", expression(value)),
+                None => decl,
+            }
+        }
+        _ => collapsed(node),
+    }
+}
+
+/// The identifier a declarator declares, `p` for `*p[3] = NULL`.
+pub fn declarator_name(node: Node<'_>) -> Option<Node<'_>> {
+    match node.kind() {
+        "identifier" | "field_identifier" | "type_identifier" => Some(node),
+        "parenthesized_declarator" | "attributed_declarator" => {
+            named_children(node).into_iter().find_map(declarator_name)
+        }
+        _ => node
+            .child_by_field_name("declarator")
+            .and_then(declarator_name),
+    }
+}
+
+/// A `parameter_list`, parentheses included.
+pub fn parameters(node: Node<'_>) -> String {
+    let params = named_children(node)
+        .into_iter()
+        .map(|param| match param.kind() {
+            "parameter_declaration" => {
+                let ty = specifiers(param);
+                match param.child_by_field_name("declarator") {
+                    Some(decl) => format!("{ty} {}", declarator(decl)),
+                    None => ty,
+                }
+            }
+            "variadic_parameter" => "...".to_string(),
+            _ => collapsed(param),
+        })
+        .collect::<Vec<_>>();
+    format!("({})", params.join(", "))
+}
+
+/// A `type_descriptor`, as found in casts and `sizeof`.
+pub fn type_descriptor(node: Node<'_>) -> String {
+    let ty = specifiers(node);
+    match node.child_by_field_name("declarator") {
+        Some(decl) => format!("{ty} {}", declarator(decl)),
+        None => ty,
+    }
+}
+
+fn operator(node: Node<'_>) -> &'static str {
+    node.child_by_field_name("operator")
+        .map(text)
+        .unwrap_or_default()
+}
+
+/// `sizeof` applied to `value`.
+///
+/// tree-sitter reads `sizeof(int) * n + 1` as `sizeof (((int)*n) + 1)`: the
+/// `sizeof` only applies to the leftmost operand, which is printed back as
+/// written.
+fn sizeof_value(keyword: &str, value: Node<'_>) -> String {
+    match value.kind() {
+        "parenthesized_expression" => format!("{keyword}{}", expression(value)),
+        "binary_expression" => {
+            let left = value
+                .child_by_field_name("left")
+                .map(|left| sizeof_value(keyword, left))
+                .unwrap_or_default();
+            let right = value
+                .child_by_field_name("right")
+                .map(expression)
+                .unwrap_or_default();
+            format!("{left} {} {right}", operator(value))
+        }
+        "cast_expression" => {
+            let ty = value
+                .child_by_field_name("type")
+                .map(type_descriptor)
+                .unwrap_or_default();
+            match value.child_by_field_name("value") {
+                Some(rest)
+                    if matches!(rest.kind(), "pointer_expression" | "unary_expression")
+                        && matches!(operator(rest), "*" | "&" | "+" | "-") =>
+                {
+                    let arg = rest
+                        .child_by_field_name("argument")
+                        .map(expression)
+                        .unwrap_or_default();
+                    format!("{keyword}({ty}) {} {arg}", operator(rest))
+                }
+                Some(rest) => format!("{keyword}({ty}){}", expression(rest)),
+                None => format!("{keyword}({ty})"),
+            }
+        }
+        _ => format!("{keyword} {}", expression(value)),
+    }
+}
+
+/// An expression, with the usual Norm spacing: spaces around binary and
+/// assignment operators, a space after commas, nothing after unary operators
+/// and casts.
+pub fn expression(node: Node<'_>) -> String {
+    let field = |name| {
+        node.child_by_field_name(name)
+            .map(expression)
+            .unwrap_or_default()
+    };
+    match node.kind() {
+        "binary_expression" | "assignment_expression" => {
+            format!("{} {} {}", field("left"), operator(node), field("right"))
+        }
+        "comma_expression" => format!("{}, {}", field("left"), field("right")),
+        "conditional_expression" => format!(
+            "{} ? {} : {}",
+            field("condition"),
+            field("consequence"),
+            field("alternative")
+        ),
+        "unary_expression" | "pointer_expression" => {
+            let op = operator(node);
+            let arg = field("argument");
+            // avoid gluing `- -a` into `--a`
+            if arg.starts_with(op) && matches!(op, "-" | "+" | "&") {
+                format!("{op} {arg}")
+            } else {
+                format!("{op}{arg}")
+            }
+        }
+        "update_expression" => {
+            let op = node.child_by_field_name("operator");
+            let arg = node.child_by_field_name("argument");
+            match (op, arg) {
+                (Some(op), Some(arg)) if op.start_byte() < arg.start_byte() => {
+                    format!("{}{}", text(op), expression(arg))
+                }
+                (Some(op), Some(arg)) => format!("{}{}", expression(arg), text(op)),
+                _ => collapsed(node),
+            }
+        }
+        "call_expression" => format!("{}{}", field("function"), field("arguments")),
+        "argument_list" => {
+            let args = named_children(node)
+                .into_iter()
+                .map(expression)
+                .collect::<Vec<_>>();
+            format!("({})", args.join(", "))
+        }
+        "field_expression" => format!("{}{}{}", field("argument"), operator(node), field("field")),
+        "subscript_expression" => format!("{}[{}]", field("argument"), field("index")),
+        "cast_expression" => format!(
+            "({}){}",
+            node.child_by_field_name("type")
+                .map(type_descriptor)
+                .unwrap_or_default(),
+            field("value")
+        ),
+        "compound_literal_expression" => format!(
+            "({}){}",
+            node.child_by_field_name("type")
+                .map(type_descriptor)
+                .unwrap_or_default(),
+            field("value")
+        ),
+        "sizeof_expression" | "alignof_expression" => {
+            let keyword = node.child(0).map(text).unwrap_or("sizeof");
+            match (
+                node.child_by_field_name("type"),
+                node.child_by_field_name("value"),
+            ) {
+                (Some(ty), _) => format!("{keyword}({})", type_descriptor(ty)),
+                (None, Some(value)) => sizeof_value(keyword, value),
+                (None, None) => collapsed(node),
+            }
+        }
+        "parenthesized_expression" => {
+            let inner = named_children(node)
+                .into_iter()
+                .map(expression)
+                .collect::<String>();
+            format!("({inner})")
+        }
+        "initializer_list" => {
+            let items = named_children(node)
+                .into_iter()
+                .map(expression)
+                .collect::<Vec<_>>();
+            format!("{{{}}}", items.join(", "))
+        }
+        "initializer_pair" => {
+            let mut cursor = node.walk();
+            let designators = node
+                .children_by_field_name("designator", &mut cursor)
+                .map(collapsed)
+                .collect::<String>();
+            format!("{designators} = {}", field("value"))
+        }
+        "preproc_defined" => format!(
+            "defined({})",
+            named_children(node)
+                .into_iter()
+                .map(text)
+                .collect::<String>()
+        ),
+        "concatenated_string" => named_children(node)
+            .into_iter()
+            .map(expression)
+            .collect::<Vec<_>>()
+            .join(" "),
+        "identifier" | "field_identifier" | "number_literal" | "string_literal"
+        | "char_literal" | "true" | "false" | "null" => text(node).to_string(),
+        _ => collapsed(node),
+    }
+}
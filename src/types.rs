@@ -0,0 +1,244 @@
+//! Printer for `struct`, `union`, `enum` and `typedef` definitions.
+
+use std::io::Write;
+
+use tree_sitter::Node;
+
+use crate::syntax::{self, collapsed, expression, text};
+use crate::{
+    align_tabs, is_trailing, trailing_comment, write_commented, CommentBlock, TypeDefinition,
+};
+
+/// A line of a type definition: either a cell that takes part in the tab
+/// alignment followed by the rest of the line, or an already laid out line,
+/// along with the comment ending it.
+enum Line {
+    Aligned(String, String, Option<&'static str>),
+    Raw(String, Option<&'static str>),
+}
+
+fn tabs(ident: usize) -> String {
+    "\t".repeat(ident)
+}
+
+/// `struct name`, `enum name`... : the keyword and the tag of a specifier.
+fn specifier_head(spec: Node<'_>) -> String {
+    let mut head = spec.child(0).map(text).unwrap_or_default().to_string();
+    if let Some(name) = spec.child_by_field_name("name") {
+        head.push(' ');
+        head.push_str(text(name));
+    }
+    head
+}
+
+/// The declarators of a node (`name, *ptr`) followed by its bitfield, if any.
+fn declarator_list(node: Node<'_>) -> String {
+    let mut out = syntax::declarators(node)
+        .into_iter()
+        .map(syntax::declarator)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut cursor = node.walk();
+    let bitfield = node
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "bitfield_clause");
+    if let Some(size) = bitfield.and_then(|b| b.named_child(0)) {
+        out.push_str(&format!(" : {}", expression(size)));
+    }
+    out
+}
+
+/// Lays out a specifier with a body: the head, the braces on their own lines,
+/// the members and the closing brace followed by `declarators` and `comment`.
+fn body_lines(
+    head: String,
+    spec: Node<'_>,
+    (declarators, comment): (String, Option<&'static str>),
+    ident: usize,
+    out: &mut Vec<Line>,
+) {
+    out.push(Line::Raw(format!("{}{head}", tabs(ident)), None));
+    out.push(Line::Raw(format!("{}{{", tabs(ident)), None));
+    let mut members = Vec::new();
+    let mut comments = CommentBlock::default();
+    let flush_comments = |comments: &mut CommentBlock<'_>, members: &mut Vec<Line>| {
+        let mut buffer = Vec::new();
+        std::mem::take(comments)
+            .format(ident + 1, &mut buffer)
+            .unwrap();
+        let buffer = String::from_utf8(buffer).unwrap();
+        members.extend(buffer.lines().map(|l| Line::Raw(l.to_string(), None)));
+    };
+    if let Some(body) = spec.child_by_field_name("body") {
+        let mut cursor = body.walk();
+        let children = body.named_children(&mut cursor).collect::<Vec<_>>();
+        let enumerators = children.iter().filter(|n| n.kind() == "enumerator").count();
+        let mut enumerator_idx = 0;
+        for member in children {
+            if member.kind() == "comment" {
+                if !is_trailing(member) {
+                    comments.0.push(member);
+                }
+                continue;
+            }
+            let comment = trailing_comment(member).map(text);
+            flush_comments(&mut comments, &mut members);
+            match member.kind() {
+                "field_declaration" => {
+                    let ty = member.child_by_field_name("type");
+                    let commented = syntax::commented_declarators(member, member.end_byte());
+                    match ty.filter(|t| t.child_by_field_name("body").is_some()) {
+                        // the comments of the body are kept by `body_lines`
+                        None if commented.is_some() => {
+                            let (ty, rest) = commented.unwrap();
+                            members.push(Line::Aligned(
+                                format!("{}{ty}\t", tabs(ident + 1)),
+                                rest,
+                                comment,
+                            ))
+                        }
+                        Some(ty) => body_lines(
+                            specifier_head(ty),
+                            ty,
+                            (declarator_list(member), comment),
+                            ident + 1,
+                            &mut members,
+                        ),
+                        None => members.push(Line::Aligned(
+                            format!("{}{}\t", tabs(ident + 1), syntax::specifiers(member)),
+                            format!("{};", declarator_list(member)),
+                            comment,
+                        )),
+                    }
+                }
+                "enumerator" if syntax::has_comment(member) => {
+                    enumerator_idx += 1;
+                    let mut line = format!("{}{}", tabs(ident + 1), text(member));
+                    if enumerator_idx != enumerators {
+                        line.push(',');
+                    }
+                    members.push(Line::Raw(line, comment));
+                }
+                "enumerator" => {
+                    enumerator_idx += 1;
+                    let name = member
+                        .child_by_field_name("name")
+                        .map(text)
+                        .unwrap_or_default();
+                    let mut line = format!("{}{name}", tabs(ident + 1));
+                    if let Some(value) = member.child_by_field_name("value") {
+                        line.push_str(&format!(" = {}", expression(value)));
+                    }
+                    if enumerator_idx != enumerators {
+                        line.push(',');
+                    }
+                    members.push(Line::Raw(line, comment));
+                }
+                _ => members.push(Line::Raw(text(member).trim_end().to_string(), comment)),
+            }
+        }
+    }
+    flush_comments(&mut comments, &mut members);
+    if declarators.is_empty() {
+        members.push(Line::Raw(format!("{}}};", tabs(ident)), comment));
+    } else {
+        members.push(Line::Aligned(
+            format!("{}}}\t", tabs(ident)),
+            format!("{declarators};"),
+            comment,
+        ));
+    }
+    align_tabs(members.iter_mut().filter_map(|line| match line {
+        Line::Aligned(cell, _, _) => Some(cell),
+        Line::Raw(..) => None,
+    }));
+    out.extend(members);
+}
+
+impl<'ts> TypeDefinition<'ts> {
+    /// Whether the definition spans several lines, i.e. has a body.
+    pub fn has_body(&self) -> bool {
+        let spec = match self.1.kind() {
+            "type_definition" | "declaration" => self.1.child_by_field_name("type"),
+            _ => Some(self.1),
+        };
+        spec.is_some_and(|s| s.child_by_field_name("body").is_some())
+    }
+
+    /// The type part and the declarators of a definition without a body,
+    /// to be aligned with the other ones of its block.
+    pub fn single_line(&self) -> (String, String) {
+        let commented = match self.1.kind() {
+            "type_definition" => syntax::commented_declarators(self.1, self.1.end_byte()),
+            _ => None,
+        };
+        if let Some((ty, rest)) = commented {
+            return (format!("{ty}\t"), rest);
+        }
+        match self.1.kind() {
+            "type_definition" => {
+                let mut ty = String::from("typedef");
+                let mut cursor = self.1.walk();
+                for child in self.1.named_children(&mut cursor) {
+                    if Some(child) == self.1.child_by_field_name("declarator") {
+                        break;
+                    }
+                    if child.kind() != "comment" {
+                        ty.push(' ');
+                        ty.push_str(&collapsed(child));
+                    }
+                }
+                ty.push('\t');
+                (ty, format!("{};", declarator_list(self.1)))
+            }
+            _ => (format!("{};", specifier_head(self.1)), String::new()),
+        }
+    }
+
+    /// Prints a definition with a body, braces on their own lines.
+    pub fn format(&self, ident: usize, fmt: &mut impl Write) -> std::io::Result<()> {
+        self.0.format(ident, fmt)?;
+        let comment = trailing_comment(self.1).map(text);
+        let mut lines = Vec::new();
+        match self.1.kind() {
+            kind @ ("type_definition" | "declaration") => {
+                let Some(spec) = self.1.child_by_field_name("type") else {
+                    return Ok(());
+                };
+                // `typedef`, `static`, `const`...
+                let mut words = match kind {
+                    "type_definition" => vec![String::from("typedef")],
+                    _ => Vec::new(),
+                };
+                let mut cursor = self.1.walk();
+                for child in self.1.named_children(&mut cursor) {
+                    if child == spec {
+                        break;
+                    }
+                    if child.kind() != "comment" {
+                        words.push(collapsed(child));
+                    }
+                }
+                words.push(specifier_head(spec));
+                let head = words.join(" ");
+                let declarators = (declarator_list(self.1), comment);
+                body_lines(head, spec, declarators, ident, &mut lines);
+            }
+            _ => body_lines(
+                specifier_head(self.1),
+                self.1,
+                (String::new(), comment),
+                ident,
+                &mut lines,
+            ),
+        }
+        let lines = lines
+            .into_iter()
+            .map(|line| match line {
+                Line::Aligned(cell, rest, comment) => (format!("{cell}{rest}"), comment),
+                Line::Raw(line, comment) => (line, comment),
+            })
+            .collect::<Vec<_>>();
+        write_commented(&lines, fmt)
+    }
+}